@@ -1,3 +1,3 @@
-LDA #0
+      LDA #0
 LOOP: ADD #1
-JMP LOOP
+      JMP LOOP
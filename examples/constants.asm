@@ -0,0 +1,6 @@
+// Named constants can be used wherever a number is expected
+.equ STEP 3
+.equ RESULT 8
+LDA #1
+ADD #STEP
+STA (RESULT)
@@ -0,0 +1,6 @@
+// Adds the two values from the data memory and stores the result behind them
+LDA (8)
+ADD (9)
+STA (A)
+.org 8
+.byte 3, 4
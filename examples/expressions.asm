@@ -0,0 +1,9 @@
+// Operands can be computed from numbers, labels and constants
+.equ LIMIT 5
+.equ BASE 3
+      LDA #(LIMIT-1)
+LOOP: SUB #1
+      STA (BASE+2)
+      BRZ LOOP+3
+      JMP LOOP
+      ADD #-3
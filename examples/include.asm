@@ -0,0 +1,4 @@
+// Uses the NEGATE macro from the library next to this file
+.include "lib/negate.asm"
+LDA #3
+NEGATE
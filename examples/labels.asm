@@ -1,3 +1,6 @@
 START: LDA #1
+// A single letter is a name too, except `A` to `F`, which are hex digits
+X:     ADD #A
+       BRC X
 // Jumps to the label start
-JMP START
+       JMP START
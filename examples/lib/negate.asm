@@ -0,0 +1,8 @@
+// A small library shared by several programs
+.equ SCRATCH 0xF
+
+.macro NEGATE
+    STA (SCRATCH)
+    LDA #0
+    SUB (SCRATCH)
+.endm
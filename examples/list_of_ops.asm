@@ -1,14 +1,14 @@
-NOP
-LDA #1
-LDA (1)
-STA (1)
-ADD #1
-ADD (1)
-SUB #1
-SUB (1)
-JMP 0
-BRZ #1
-BRC #1
-BRN #1
+         NOP
+         LDA #1
+         LDA (1)
+         STA (1)
+         ADD #1
+         ADD (1)
+         SUB #1
+         SUB (1)
+         JMP 0
+         BRZ #1
+         BRC #1
+         BRN #1
 MYLABEL: NOP
-JMP MYLABEL
+         JMP MYLABEL
@@ -0,0 +1,20 @@
+// Macros are expanded in place, labels inside them are local to every call
+.macro NEGATE CELL
+          STA (CELL)
+          LDA #0
+          SUB (CELL)
+.endm
+
+.macro COUNTDOWN FROM
+          LDA #FROM
+    again:
+          SUB #1
+          BRZ done
+          JMP again
+    done: NOP
+.endm
+
+LDA #3
+NEGATE 0xF
+COUNTDOWN 2
+COUNTDOWN 1+1
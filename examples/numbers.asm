@@ -0,0 +1,6 @@
+// Numbers can be written in decimal, hex or binary
+LDA #12
+ADD #0b0011
+SUB #0xC
+ADD #-4
+STA (0xF)
@@ -0,0 +1,7 @@
+// Counts down from 3 using pseudo-instructions
+      CLR
+      ADD #3
+LOOP: DEC
+      BRZ END
+      BRA LOOP
+END:  HLT
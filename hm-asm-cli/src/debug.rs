@@ -0,0 +1,373 @@
+use hm_asm_simulator::{
+    asm::Ast,
+    disassemble::disassemble,
+    generate::Program,
+    parse::parse_literal,
+    simulate::{Breakpoint, Flag, Simulator, StateRegister, Watch, Watchpoint},
+    source::Sources,
+};
+
+use std::io::{self, BufRead, Write};
+
+static HELP: &str = "\
+step, s [n]            run n half cycles, 1 by default
+next, n [n]            run n instructions, 1 by default
+continue, c [max]      run until a breakpoint, a watchpoint or a halt, at most max instructions (1000)
+back, b [n]            undo n instructions, 1 by default
+goto STEP              move to the state after STEP instructions
+break, br [ADDR] [F=V] stop before the instruction at ADDR, a number, label or constant, and/or when flag F
+                       (c, z or n) is V, lists the breakpoints without arguments
+watch, w ADDR [KIND]   stop after an access to the data memory cell ADDR, KIND is read, write or
+                       change (the default), lists the watchpoints without arguments
+delete break|watch N   remove the breakpoint or watchpoint N
+print, p               print the registers
+memory, x              print both memories
+set REG VALUE          set pc, akku or a flag (c, z or n)
+set (ADDR) VALUE       set a data memory cell
+disassemble, d [n]     disassemble n instructions around the next one, 3 by default
+list, l                show the source line of the next instruction
+help, h                show this help
+quit, q                leave the debugger";
+
+/// Runs the interactive debugger for `program`, which was assembled from `ast`, on stdin.
+pub fn debug(sources: &Sources, ast: &Ast, program: &Program) {
+    let mut debugger = Debugger {
+        simulator: Simulator::new(program),
+        sources,
+        ast,
+    };
+    println!(
+        "Debugging {}, type `help` for the commands.",
+        sources.file(0).name
+    );
+    debugger.list();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(hm-asm) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if let "quit" | "q" = words[0] {
+            break;
+        }
+
+        let events = debugger.simulator.events().len();
+        if let Err(message) = debugger.execute(&words) {
+            eprintln!("error: {}", message);
+        }
+        for event in debugger.simulator.events().iter().skip(events) {
+            eprintln!("warning: {}", event);
+        }
+    }
+}
+
+struct Debugger<'a> {
+    simulator: Simulator,
+    sources: &'a Sources,
+    ast: &'a Ast<'a>,
+}
+
+impl<'a> Debugger<'a> {
+    fn execute(&mut self, words: &[&str]) -> Result<(), String> {
+        let arguments = &words[1..];
+        match words[0] {
+            "step" | "s" => {
+                for _ in 0..count(arguments, 1)? {
+                    self.simulator.step_half_cycle();
+                }
+                self.list();
+            }
+            "next" | "n" => {
+                for _ in 0..count(arguments, 1)? {
+                    self.simulator.step_instruction();
+                }
+                self.list();
+            }
+            "continue" | "c" => {
+                let (_, stop) = self.simulator.run(count(arguments, 1000)?);
+                println!("stopped: {}", stop);
+                self.list();
+            }
+            "back" | "b" => {
+                // An instruction that was only fetched, e.g. at a breakpoint, does not count.
+                if !self.simulator.clk() {
+                    self.simulator.step_back_half_cycle();
+                }
+                for _ in 0..count(arguments, 1)? {
+                    if self.simulator.step_back_instruction().is_none() {
+                        println!("at the start of the program");
+                        break;
+                    }
+                }
+                self.list();
+            }
+            "goto" => match arguments {
+                [step] => {
+                    let step = step
+                        .parse()
+                        .map_err(|_| format!("`{}` is not a step", step))?;
+                    self.simulator.goto_step(step);
+                    self.list();
+                }
+                _ => return Err("usage: goto STEP".to_string()),
+            },
+            "break" | "br" => self.add_breakpoint(arguments)?,
+            "watch" | "w" => self.add_watchpoint(arguments)?,
+            "delete" => match arguments {
+                [kind, index] => {
+                    let index: usize = index
+                        .parse()
+                        .map_err(|_| format!("`{}` is not a number", index))?;
+                    match *kind {
+                        "break" if index < self.simulator.breakpoints().len() => {
+                            self.simulator.remove_breakpoint(index);
+                        }
+                        "watch" if index < self.simulator.watchpoints().len() => {
+                            self.simulator.remove_watchpoint(index);
+                        }
+                        "break" | "watch" => {
+                            return Err(format!("there is no {}point {}", kind, index))
+                        }
+                        _ => return Err("usage: delete break|watch N".to_string()),
+                    }
+                }
+                _ => return Err("usage: delete break|watch N".to_string()),
+            },
+            "print" | "p" => self.print(),
+            "memory" | "x" => self.memory(),
+            "set" => self.set(arguments)?,
+            "disassemble" | "d" => self.disassemble(count(arguments, 3)?),
+            "list" | "l" => self.list(),
+            "help" | "h" => println!("{}", HELP),
+            command => {
+                return Err(format!(
+                    "unknown command `{}`, type `help` for the commands",
+                    command
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, arguments: &[&str]) -> Result<(), String> {
+        if arguments.is_empty() {
+            for (index, breakpoint) in self.simulator.breakpoints().iter().enumerate() {
+                print!("{}:", index);
+                if let Some(address) = breakpoint.address {
+                    print!(" address {}", address);
+                }
+                if let Some((flag, value)) = breakpoint.condition {
+                    print!(" when {:?} is {}", flag, value as u8);
+                }
+                println!();
+            }
+            return Ok(());
+        }
+
+        let mut breakpoint = Breakpoint {
+            address: None,
+            condition: None,
+        };
+        for argument in arguments {
+            match argument.split_once('=') {
+                Some((flag, value)) => {
+                    breakpoint.condition = Some((parse_flag(flag)?, parse_bit(value)?));
+                }
+                None => breakpoint.address = Some(self.address(argument)?),
+            }
+        }
+        self.simulator.add_breakpoint(breakpoint);
+        Ok(())
+    }
+
+    fn add_watchpoint(&mut self, arguments: &[&str]) -> Result<(), String> {
+        let (address, watch) = match arguments {
+            [] => {
+                for (index, watchpoint) in self.simulator.watchpoints().iter().enumerate() {
+                    println!(
+                        "{}: address {}, {:?}",
+                        index, watchpoint.address, watchpoint.watch
+                    );
+                }
+                return Ok(());
+            }
+            [address] => (address, Watch::Change),
+            [address, "read"] => (address, Watch::Read),
+            [address, "write"] => (address, Watch::Write),
+            [address, "change"] => (address, Watch::Change),
+            _ => return Err("usage: watch ADDR [read|write|change]".to_string()),
+        };
+        let address = self.address(address)?;
+        self.simulator.add_watchpoint(Watchpoint { address, watch });
+        Ok(())
+    }
+
+    fn set(&mut self, arguments: &[&str]) -> Result<(), String> {
+        let (target, value) = match arguments {
+            [target, value] => (*target, parse_number(value)?),
+            _ => return Err("usage: set REG VALUE or set (ADDR) VALUE".to_string()),
+        };
+        let mut sr: StateRegister = self.simulator.sr();
+        match target {
+            "pc" => self.simulator.set_pc(value),
+            "akku" => self.simulator.set_akku(value),
+            "c" | "z" | "n" => {
+                let value = value != 0;
+                match parse_flag(target)? {
+                    Flag::Carry => sr.carry = value,
+                    Flag::Zero => sr.zero = value,
+                    Flag::Negative => sr.negative = value,
+                }
+                self.simulator.set_sr(sr);
+            }
+            _ if target.starts_with('(') && target.ends_with(')') => {
+                let address = self.address(&target[1..target.len() - 1])?;
+                self.simulator.set_data_memory(address, value);
+            }
+            _ => {
+                return Err(format!(
+                    "`{}` is neither a register nor a memory cell",
+                    target
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn print(&self) {
+        let state = self.simulator.state();
+        println!(
+            "step {}, clk {}, pc {}, akku {}, ir {}, dr {}, C {}, Z {}, N {}, address bus {}, data bus {}",
+            self.simulator.step(),
+            state.clk as u8,
+            state.pc,
+            state.akku,
+            state.ir,
+            state.dr,
+            state.sr.carry as u8,
+            state.sr.zero as u8,
+            state.sr.negative as u8,
+            state.addr_bus,
+            state.data_bus
+        );
+    }
+
+    fn memory(&self) {
+        let row = |cells: &[u8; 16]| {
+            cells
+                .iter()
+                .map(|cell| format!("{:x}", cell))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        println!("address  0 1 2 3 4 5 6 7 8 9 a b c d e f");
+        println!("data     {}", row(self.simulator.data_memory()));
+        println!("program  {}", row(self.simulator.program_memory()));
+    }
+
+    /// Prints up to `around` instructions before and after the next one, disassembled from the
+    /// memories as they are now.
+    fn disassemble(&self, around: usize) {
+        let instructions = disassemble(&self.simulator.program());
+        let pc = self.simulator.instruction_address() as usize;
+        if pc >= instructions.len() {
+            println!("{} is past the end of the program", pc);
+        }
+        let first = pc.saturating_sub(around);
+        for (address, instruction) in instructions
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(2 * around + 1)
+        {
+            let labels: String = instruction
+                .labels()
+                .iter()
+                .map(|label| format!("{}: ", label.name))
+                .collect();
+            let marker = if address == pc { "=>" } else { "  " };
+            println!("{} {:x}  {:4}{}", marker, address, labels, instruction);
+        }
+    }
+
+    /// Prints where the program is and the source line of the next instruction.
+    fn list(&self) {
+        let pc = self.simulator.instruction_address();
+        let source = self.ast.instructions.get(pc as usize).map(|instruction| {
+            let span = instruction.span();
+            let file = self.sources.file(span.file);
+            let line = file.content.lines().nth(span.line - 1).unwrap_or("").trim();
+            format!("{}:{}: {}", file.name, span.line, line)
+        });
+        println!(
+            "step {}, address {}: {}",
+            self.simulator.step(),
+            pc,
+            source.unwrap_or_else(|| "past the end of the program".to_string())
+        );
+    }
+
+    /// An address given as a number or the name of a label or constant.
+    fn address(&self, word: &str) -> Result<u8, String> {
+        if let Some(label) = self.ast.label(word) {
+            return Ok(label.location);
+        }
+        match self
+            .ast
+            .constants
+            .iter()
+            .find(|constant| constant.name == word)
+        {
+            Some(constant) if constant.value.value <= 15 => Ok(constant.value.value),
+            Some(constant) => Err(format!(
+                "`{}` has the value {}, which is not an address",
+                word, constant.value.value
+            )),
+            None => parse_number(word),
+        }
+    }
+}
+
+/// The optional count that is the only argument of a command.
+fn count(arguments: &[&str], default: usize) -> Result<usize, String> {
+    match arguments {
+        [] => Ok(default),
+        [count] => count
+            .parse()
+            .map_err(|_| format!("`{}` is not a count", count)),
+        _ => Err("expected at most one count".to_string()),
+    }
+}
+
+/// A value for a four bit register or memory cell, written like a number in the assembler.
+fn parse_number(word: &str) -> Result<u8, String> {
+    match parse_literal(word) {
+        Some(value @ 0..=15) => Ok(value as u8),
+        _ => Err(format!("`{}` is not a number from 0 to 15", word)),
+    }
+}
+
+fn parse_flag(word: &str) -> Result<Flag, String> {
+    match word {
+        "c" | "C" => Ok(Flag::Carry),
+        "z" | "Z" => Ok(Flag::Zero),
+        "n" | "N" => Ok(Flag::Negative),
+        _ => Err(format!("`{}` is not a flag, use c, z or n", word)),
+    }
+}
+
+fn parse_bit(word: &str) -> Result<bool, String> {
+    match word {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("`{}` is neither 0 nor 1", word)),
+    }
+}
@@ -0,0 +1,34 @@
+use hm_asm_simulator::generate::Program;
+
+/// Reads a memory dump in the format `compile` prints: the 16 data memory cells followed by the
+/// 16 program memory cells, one hex digit each.
+///
+/// Digits may be separated by whitespace or not, lines with anything else in them, like the
+/// `Data Memory:` headings, are skipped.
+pub fn parse_dump(dump: &str) -> Result<Program, String> {
+    let digits: Vec<u8> = dump
+        .lines()
+        .filter(|line| {
+            line.split_whitespace()
+                .all(|word| word.chars().all(|c| c.is_ascii_hexdigit()))
+        })
+        .flat_map(|line| line.chars().filter_map(|c| c.to_digit(16)))
+        .map(|digit| digit as u8)
+        .collect();
+
+    if digits.len() != 32 {
+        return Err(format!(
+            "a dump needs 32 hex digits, 16 for each memory, but this one has {}",
+            digits.len()
+        ));
+    }
+
+    let mut data_memory = [0; 16];
+    let mut program_memory = [0; 16];
+    data_memory.copy_from_slice(&digits[..16]);
+    program_memory.copy_from_slice(&digits[16..]);
+    Ok(Program {
+        data_memory,
+        program_memory,
+    })
+}
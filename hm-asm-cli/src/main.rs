@@ -1,16 +1,28 @@
 extern crate clap;
 
 use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 
 use hm_asm_simulator::{
-    generate::generate_binary,
-    parse::{parse_asm, AsmParser, Rule},
-    simulate::simulate
+    diagnostics::Diagnostic,
+    disassemble::{disassemble, disassemble_data, lost_operands, print_asm},
+    format::format_source,
+    generate::{generate_binary, Memory, Program},
+    listing::listing,
+    parse::parse_asm,
+    simulate::{simulate_program_with_events, Simulator},
+    source::{FsLoader, Sources},
+    Error,
 };
 
-use pest::Parser;
 use clap::{Arg, App, SubCommand};
 
+mod debug;
+use debug::debug;
+mod dump;
+use dump::parse_dump;
 mod html;
 use html::html_state_table;
 
@@ -24,28 +36,191 @@ fn main() {
             .arg(Arg::with_name("COMP_FILE")
                 .help("Sets the asm file to compile")
                 .value_name("FILE") 
-                .takes_value(true)))
+                .takes_value(true))
+            .arg(Arg::with_name("listing")
+                .long("listing")
+                .help("Prints a listing with the encoding and source of every instruction and a symbol table"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .help("Prints the memories in the given format instead of as a grid")
+                .takes_value(true)
+                .possible_values(&["hex", "raw", "packed", "logisim", "readmemh", "vhdl", "verilog"]))
+            .arg(Arg::with_name("memory")
+                .long("memory")
+                .help("The memory to print with the formats that hold a single one")
+                .takes_value(true)
+                .possible_values(&["data", "program"])
+                .requires("format")
+                .required_ifs(&[
+                    ("format", "hex"),
+                    ("format", "raw"),
+                    ("format", "packed"),
+                    ("format", "logisim"),
+                    ("format", "readmemh"),
+                ])))
         .subcommand(SubCommand::with_name("simulate")
             .arg(Arg::with_name("SIM_FILE")
                 .help("Sets the asm file to simulate")
                 .value_name("FILE") 
-                .takes_value(true))
+                .takes_value(true)
+                .required(true))
             .arg(Arg::with_name("cycles")
                 .help("How many cycles to run the simulator for")
                 .value_name("cycles")
-                .takes_value(true)))
+                .takes_value(true)
+                .required_unless("until-halt"))
+            .arg(Arg::with_name("until-halt")
+                .long("until-halt")
+                .help("Runs until the program halts instead of for a number of cycles")
+                .conflicts_with("cycles"))
+            .arg(Arg::with_name("max")
+                .long("max")
+                .help("How many cycles to run at most with --until-halt, 1000 by default")
+                .value_name("N")
+                .takes_value(true)
+                .requires("until-halt"))
+            .arg(Arg::with_name("dump")
+                .long("dump")
+                .help("Reads FILE as a memory dump in the format compile prints instead of as asm")))
+        .subcommand(SubCommand::with_name("disassemble")
+            .arg(Arg::with_name("DUMP_FILE")
+                .help("Sets the memory dump to disassemble, in the format compile prints")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("debug")
+            .arg(Arg::with_name("DEBUG_FILE")
+                .help("Sets the asm file to debug")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("fmt")
+            .arg(Arg::with_name("FMT_FILE")
+                .help("Sets the asm file to format in place")
+                .value_name("FILE")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("check")
+                .long("check")
+                .help("Only checks whether the file is formatted, exits with 1 if it is not")))
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("compile") {
-        let file_content = fs::read_to_string(matches.value_of("COMP_FILE").unwrap()).expect("Could not read the provided asm file");
-        let instructions = parse_asm(AsmParser::parse(Rule::program, &file_content).unwrap_or_else(|e| panic!("{}", e)));
-        let binary = generate_binary(instructions);
-        println!("{}", binary);
+        let sources = load(matches.value_of("COMP_FILE").unwrap());
+        let ast = parse_asm(&sources).unwrap_or_else(|e| exit_with(&e, &sources));
+        let (binary, warnings) = generate_binary(&ast).unwrap_or_else(|e| exit_with(&e, &sources));
+        for warning in warnings.iter() {
+            eprint!("{}", Diagnostic::from(warning).render(&sources));
+        }
+        if matches.is_present("listing") {
+            print!("{}", listing(&ast, &binary, &sources).unwrap_or_else(|e| exit_with(&e, &sources)));
+        } else if let Some(format) = matches.value_of("format") {
+            let file_name = matches.value_of("COMP_FILE").unwrap();
+            let memory = match matches.value_of("memory") {
+                Some("data") => Memory::Data,
+                _ => Memory::Program,
+            };
+            match format {
+                "vhdl" => print!("{}", binary.vhdl_package(&hdl_name(file_name))),
+                "verilog" => print!("{}", binary.verilog_rom(&hdl_name(file_name))),
+                "readmemh" => print!("{}", binary.readmemh(memory)),
+                "hex" => print!("{}", binary.intel_hex(memory)),
+                "raw" => io::stdout().write_all(&binary.raw(memory)).expect("Could not write the memory"),
+                "packed" => io::stdout().write_all(&binary.packed(memory)).expect("Could not write the memory"),
+                _ => print!("{}", binary.logisim(memory)),
+            }
+        } else {
+            println!("{}", binary);
+        }
     } else if let Some(matches) = matches.subcommand_matches("simulate") {
-        let file_content = fs::read_to_string(matches.value_of("SIM_FILE").unwrap()).expect("Could not read the provided asm file");
-        let instructions = parse_asm(AsmParser::parse(Rule::program, &file_content).unwrap_or_else(|e| panic!("{}", e)));
-        let states = simulate(instructions, matches.value_of("cycles").unwrap().parse::<usize>().unwrap());
+        let file_name = matches.value_of("SIM_FILE").unwrap();
+        let program = if matches.is_present("dump") {
+            load_dump(file_name)
+        } else {
+            let sources = load(file_name);
+            let (program, _) = parse_asm(&sources).and_then(|ast| generate_binary(&ast)).unwrap_or_else(|e| exit_with(&e, &sources));
+            program
+        };
+        let (states, events) = if matches.is_present("until-halt") {
+            let max = matches.value_of("max").map_or(1000, |max| max.parse::<usize>().expect("max has to be a number"));
+            let mut simulator = Simulator::new(&program);
+            let (states, stop) = simulator.run(max);
+            eprintln!("stopped after {} cycles: {}", simulator.step(), stop);
+            (states, simulator.events().to_vec())
+        } else {
+            let cycles = matches.value_of("cycles").unwrap().parse::<usize>().expect("cycles has to be a number");
+            simulate_program_with_events(&program, cycles)
+        };
+        for event in events.iter() {
+            eprintln!("warning: {}", event);
+        }
         //println!("{:#?}", states);
         println!("{}", html_state_table(states));
+    } else if let Some(matches) = matches.subcommand_matches("disassemble") {
+        let program = load_dump(matches.value_of("DUMP_FILE").unwrap());
+        for item in lost_operands(&program) {
+            eprintln!(
+                "warning: the NOP at address {} has the operand {}, which is lost",
+                item.address, item.value.value
+            );
+        }
+        print!("{}", print_asm(&disassemble(&program), &disassemble_data(&program)));
+    } else if let Some(matches) = matches.subcommand_matches("debug") {
+        let sources = load(matches.value_of("DEBUG_FILE").unwrap());
+        let ast = parse_asm(&sources).unwrap_or_else(|e| exit_with(&e, &sources));
+        let (program, _) = generate_binary(&ast).unwrap_or_else(|e| exit_with(&e, &sources));
+        debug(&sources, &ast, &program);
+    } else if let Some(matches) = matches.subcommand_matches("fmt") {
+        let file_name = matches.value_of("FMT_FILE").unwrap();
+        let file_content = fs::read_to_string(file_name).expect("Could not read the provided asm file");
+        let formatted = format_source(&file_content).unwrap_or_else(|e| {
+            let mut sources = Sources::new();
+            sources.add(file_name, &file_content);
+            exit_with(&e, &sources)
+        });
+        if matches.is_present("check") {
+            if formatted != file_content {
+                eprintln!("{} is not formatted", file_name);
+                process::exit(1);
+            }
+        } else if formatted != file_content {
+            fs::write(file_name, formatted).expect("Could not write the formatted asm file");
+        }
+    }
+}
+
+/// Reads `file_name` and every file it includes.
+fn load(file_name: &str) -> Sources {
+    let file_content = fs::read_to_string(file_name).expect("Could not read the provided asm file");
+    let mut sources = Sources::new();
+    let root = sources.add(file_name, &file_content);
+    if let Err(e) = sources.load_includes(root, &FsLoader) {
+        exit_with(&e, &sources)
+    }
+    sources
+}
+
+/// Reads the memory dump `file_name`.
+fn load_dump(file_name: &str) -> Program {
+    let dump = fs::read_to_string(file_name).expect("Could not read the provided dump file");
+    parse_dump(&dump).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    })
+}
+
+/// A VHDL and Verilog identifier for the design generated from `file_name`.
+fn hdl_name(file_name: &str) -> String {
+    let stem = Path::new(file_name).file_stem().map_or("".into(), |stem| stem.to_string_lossy());
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name
+    } else {
+        format!("rom_{}", name)
     }
 }
+
+fn exit_with<T>(e: &Error, sources: &Sources) -> T {
+    eprint!("{}", Diagnostic::from(e).render(sources));
+    process::exit(1);
+}
@@ -1,29 +1,208 @@
+use crate::source::FileId;
+
+use std::fmt;
+
+/// A region of the source text, used to point diagnostics at the code they are about.
+///
+/// `line` and `column` are one based and refer to the start of the region, `start` and
+/// `end` are byte offsets into the source `file`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file: FileId,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(span: pest::Span, file: FileId) -> Self {
+        let (line, column) = span.start_pos().line_col();
+        Span {
+            file,
+            line,
+            column,
+            start: span.start(),
+            end: span.end(),
+        }
+    }
+}
+
+/// A span in the first file of a program.
+impl<'a> From<pest::Span<'a>> for Span {
+    fn from(span: pest::Span<'a>) -> Self {
+        Span::new(span, 0)
+    }
+}
+
+/// An operand value together with the place in the source it was written at.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum Argument {
-    MemoryLocation(u8),
-    Constant(u8),
+pub struct Operand<T> {
+    pub value: T,
+    pub span: Span,
+}
+
+/// A constant expression as written in an operand, evaluated once all symbols are known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr<'a> {
+    Number(i32),
+    Symbol(Operand<&'a str>),
+    Neg(Box<Expr<'a>>),
+    Add(Box<Expr<'a>>, Box<Expr<'a>>),
+    Sub(Box<Expr<'a>>, Box<Expr<'a>>),
+}
+
+/// Prints the expression as it can be written in an operand.
+impl<'a> fmt::Display for Expr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Operators are left associative, so only a compound right hand side needs parentheses.
+        fn operand(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+            match expr {
+                Expr::Add(..) | Expr::Sub(..) => write!(f, "({})", expr),
+                _ => write!(f, "{}", expr),
+            }
+        }
+
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name.value),
+            Expr::Neg(expr) => {
+                write!(f, "-")?;
+                operand(f, expr)
+            }
+            Expr::Add(lhs, rhs) => {
+                write!(f, "{}+", lhs)?;
+                operand(f, rhs)
+            }
+            Expr::Sub(lhs, rhs) => {
+                write!(f, "{}-", lhs)?;
+                operand(f, rhs)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Argument<'a> {
+    MemoryLocation(Operand<Expr<'a>>),
+    Constant(Operand<Expr<'a>>),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Label<'a> {
     pub name: &'a str,
     pub location: u8,
+    pub span: Span,
 }
 
+/// A named value defined with `.equ NAME value`, `span` is the one of the name.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Constant<'a> {
+    pub name: &'a str,
+    pub value: Operand<u8>,
+    pub span: Span,
+}
+
+/// The target of a relative branch, either a raw `#n` offset or the address to compute it from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BranchArgument<'a> {
+    Offset(Operand<Expr<'a>>),
+    Target(Operand<Expr<'a>>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum JumpArgument<'a> {
-    Location(u8),
-    Label(&'a str),
-    MemoryLocation(u8),
+    Location(Operand<Expr<'a>>),
+    MemoryLocation(Operand<Expr<'a>>),
+    /// The `#n` offset of a `BRA`, the target is `n` instructions on, modulo 16 like a branch.
+    Offset(Operand<Expr<'a>>),
+}
+
+/// The result of parsing a program: its instructions, the initial contents of the data memory
+/// and the constants defined with `.equ`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ast<'a> {
+    pub instructions: Vec<Instruction<'a>>,
+    pub data: Vec<DataItem>,
+    pub constants: Vec<Constant<'a>>,
+}
+
+impl<'a> Ast<'a> {
+    /// The label called `name`, e.g. to set a breakpoint on it.
+    pub fn label(&self, name: &str) -> Option<&Label<'a>> {
+        self.instructions
+            .iter()
+            .flat_map(|instruction| instruction.labels())
+            .find(|label| label.name == name)
+    }
 }
 
+/// A value placed into the data memory by a `.byte` directive.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DataItem {
+    pub address: u8,
+    pub value: Operand<u8>,
+}
+
+/// A single parsed instruction, the labels pointing at it and the span of the instruction itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instruction<'a> {
-    NoArgumentInstruction(NoArgumentInstruction, Option<Label<'a>>),
-    MemoryLocationInstruction(MemoryLocationInstruction, Option<Label<'a>>),
-    ConstantArgumentInstruction(ConstantArgumentInstruction, Option<Label<'a>>),
-    ArgumentInstruction(ArgumentInstruction, Option<Label<'a>>),
-    Jump(JumpArgument<'a>, Option<Label<'a>>),
+    NoArgumentInstruction(NoArgumentInstruction, Vec<Label<'a>>, Span),
+    MemoryLocationInstruction(MemoryLocationInstruction<'a>, Vec<Label<'a>>, Span),
+    ConstantArgumentInstruction(ConstantArgumentInstruction<'a>, Vec<Label<'a>>, Span),
+    ArgumentInstruction(ArgumentInstruction<'a>, Vec<Label<'a>>, Span),
+    Jump(JumpArgument<'a>, Vec<Label<'a>>, Span),
+}
+
+impl<'a> Instruction<'a> {
+    pub fn labels(&self) -> &[Label<'a>] {
+        match self {
+            Instruction::NoArgumentInstruction(_, labels, _)
+            | Instruction::MemoryLocationInstruction(_, labels, _)
+            | Instruction::ConstantArgumentInstruction(_, labels, _)
+            | Instruction::ArgumentInstruction(_, labels, _)
+            | Instruction::Jump(_, labels, _) => labels,
+        }
+    }
+
+    /// The operand expression of the instruction, `None` for `NOP`.
+    pub fn operand_mut(&mut self) -> Option<&mut Operand<Expr<'a>>> {
+        match self {
+            Instruction::NoArgumentInstruction(..) => None,
+            Instruction::MemoryLocationInstruction(MemoryLocationInstruction::STA(arg), _, _) => {
+                Some(arg)
+            }
+            Instruction::ConstantArgumentInstruction(instruction, _, _) => match instruction {
+                ConstantArgumentInstruction::BRZ(arg)
+                | ConstantArgumentInstruction::BRC(arg)
+                | ConstantArgumentInstruction::BRN(arg) => match arg {
+                    BranchArgument::Offset(arg) | BranchArgument::Target(arg) => Some(arg),
+                },
+            },
+            Instruction::ArgumentInstruction(instruction, _, _) => match instruction {
+                ArgumentInstruction::LDA(arg)
+                | ArgumentInstruction::ADD(arg)
+                | ArgumentInstruction::SUB(arg) => match arg {
+                    Argument::MemoryLocation(arg) | Argument::Constant(arg) => Some(arg),
+                },
+            },
+            Instruction::Jump(arg, _, _) => match arg {
+                JumpArgument::Location(arg)
+                | JumpArgument::MemoryLocation(arg)
+                | JumpArgument::Offset(arg) => Some(arg),
+            },
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Instruction::NoArgumentInstruction(_, _, span)
+            | Instruction::MemoryLocationInstruction(_, _, span)
+            | Instruction::ConstantArgumentInstruction(_, _, span)
+            | Instruction::ArgumentInstruction(_, _, span)
+            | Instruction::Jump(_, _, span) => *span,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -31,104 +210,137 @@ pub enum NoArgumentInstruction {
     NOP,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum MemoryLocationInstruction {
-    STA(u8),
+impl NoArgumentInstruction {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            NoArgumentInstruction::NOP => 0,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum ConstantArgumentInstruction {
-    BRZ(u8),
-    BRC(u8),
-    BRN(u8),
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemoryLocationInstruction<'a> {
+    STA(Operand<Expr<'a>>),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum ArgumentInstruction {
-    LDA(Argument),
-    ADD(Argument),
-    SUB(Argument),
+impl<'a> MemoryLocationInstruction<'a> {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            MemoryLocationInstruction::STA(_) => 3,
+        }
+    }
+
+    pub fn argument(&self) -> &Operand<Expr<'a>> {
+        match self {
+            MemoryLocationInstruction::STA(arg) => arg,
+        }
+    }
 }
 
-pub struct BinaryInstruction {
-    pub opcode: u8,
-    pub argument: u8,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConstantArgumentInstruction<'a> {
+    BRZ(BranchArgument<'a>),
+    BRC(BranchArgument<'a>),
+    BRN(BranchArgument<'a>),
 }
 
-impl<'a> Into<BinaryInstruction> for NoArgumentInstruction {
-    fn into(self) -> BinaryInstruction {
+impl<'a> ConstantArgumentInstruction<'a> {
+    pub fn opcode(&self) -> u8 {
         match self {
-            NoArgumentInstruction::NOP => BinaryInstruction {
-                opcode: 0,
-                argument: 0,
-            },
+            ConstantArgumentInstruction::BRZ(_) => 9,
+            ConstantArgumentInstruction::BRC(_) => 10,
+            ConstantArgumentInstruction::BRN(_) => 11,
         }
     }
-}
 
-impl<'a> Into<BinaryInstruction> for MemoryLocationInstruction {
-    fn into(self) -> BinaryInstruction {
+    pub fn argument(&self) -> &BranchArgument<'a> {
         match self {
-            MemoryLocationInstruction::STA(arg) => BinaryInstruction {
-                opcode: 3,
-                argument: arg,
-            },
+            ConstantArgumentInstruction::BRZ(arg)
+            | ConstantArgumentInstruction::BRC(arg)
+            | ConstantArgumentInstruction::BRN(arg) => arg,
         }
     }
 }
 
-impl<'a> Into<BinaryInstruction> for ConstantArgumentInstruction {
-    fn into(self) -> BinaryInstruction {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArgumentInstruction<'a> {
+    LDA(Argument<'a>),
+    ADD(Argument<'a>),
+    SUB(Argument<'a>),
+}
+
+impl<'a> ArgumentInstruction<'a> {
+    pub fn opcode(&self) -> u8 {
         match self {
-            ConstantArgumentInstruction::BRZ(arg) => BinaryInstruction {
-                opcode: 9,
-                argument: arg,
-            },
-            ConstantArgumentInstruction::BRC(arg) => BinaryInstruction {
-                opcode: 10,
-                argument: arg,
-            },
-            ConstantArgumentInstruction::BRN(arg) => BinaryInstruction {
-                opcode: 11,
-                argument: arg,
+            ArgumentInstruction::LDA(Argument::Constant(_)) => 1,
+            ArgumentInstruction::LDA(Argument::MemoryLocation(_)) => 2,
+            ArgumentInstruction::ADD(Argument::Constant(_)) => 4,
+            ArgumentInstruction::ADD(Argument::MemoryLocation(_)) => 5,
+            ArgumentInstruction::SUB(Argument::Constant(_)) => 6,
+            ArgumentInstruction::SUB(Argument::MemoryLocation(_)) => 7,
+        }
+    }
+
+    pub fn argument(&self) -> &Operand<Expr<'a>> {
+        match self {
+            ArgumentInstruction::LDA(arg)
+            | ArgumentInstruction::ADD(arg)
+            | ArgumentInstruction::SUB(arg) => match arg {
+                Argument::MemoryLocation(arg) | Argument::Constant(arg) => arg,
             },
         }
     }
 }
 
-impl<'a> Into<BinaryInstruction> for ArgumentInstruction {
-    fn into(self) -> BinaryInstruction {
+/// Prints the instruction without its labels, e.g. `LDA #1`.
+impl<'a> fmt::Display for Instruction<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ArgumentInstruction::LDA(arg) => match arg {
-                Argument::MemoryLocation(arg) => BinaryInstruction {
-                    opcode: 2,
-                    argument: arg,
-                },
-                Argument::Constant(arg) => BinaryInstruction {
-                    opcode: 1,
-                    argument: arg,
-                },
-            },
-            ArgumentInstruction::ADD(arg) => match arg {
-                Argument::MemoryLocation(arg) => BinaryInstruction {
-                    opcode: 5,
-                    argument: arg,
-                },
-                Argument::Constant(arg) => BinaryInstruction {
-                    opcode: 4,
-                    argument: arg,
-                },
-            },
-            ArgumentInstruction::SUB(arg) => match arg {
-                Argument::MemoryLocation(arg) => BinaryInstruction {
-                    opcode: 7,
-                    argument: arg,
-                },
-                Argument::Constant(arg) => BinaryInstruction {
-                    opcode: 6,
-                    argument: arg,
-                },
-            },
+            Instruction::NoArgumentInstruction(NoArgumentInstruction::NOP, _, _) => {
+                write!(f, "NOP")
+            }
+            Instruction::MemoryLocationInstruction(MemoryLocationInstruction::STA(arg), _, _) => {
+                write!(f, "STA ({})", arg.value)
+            }
+            Instruction::ConstantArgumentInstruction(instruction, _, _) => {
+                let mnemonic = match instruction {
+                    ConstantArgumentInstruction::BRZ(_) => "BRZ",
+                    ConstantArgumentInstruction::BRC(_) => "BRC",
+                    ConstantArgumentInstruction::BRN(_) => "BRN",
+                };
+                match instruction.argument() {
+                    BranchArgument::Offset(offset) => write!(f, "{} #{}", mnemonic, offset.value),
+                    BranchArgument::Target(target) => write!(f, "{} {}", mnemonic, target.value),
+                }
+            }
+            Instruction::ArgumentInstruction(instruction, _, _) => {
+                let (mnemonic, arg) = match instruction {
+                    ArgumentInstruction::LDA(arg) => ("LDA", arg),
+                    ArgumentInstruction::ADD(arg) => ("ADD", arg),
+                    ArgumentInstruction::SUB(arg) => ("SUB", arg),
+                };
+                match arg {
+                    Argument::MemoryLocation(address) => {
+                        write!(f, "{} ({})", mnemonic, address.value)
+                    }
+                    Argument::Constant(constant) => write!(f, "{} #{}", mnemonic, constant.value),
+                }
+            }
+            Instruction::Jump(JumpArgument::Location(location), _, _) => {
+                write!(f, "JMP {}", location.value)
+            }
+            Instruction::Jump(JumpArgument::MemoryLocation(address), _, _) => {
+                write!(f, "JMP ({})", address.value)
+            }
+            Instruction::Jump(JumpArgument::Offset(offset), _, _) => {
+                write!(f, "BRA #{}", offset.value)
+            }
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BinaryInstruction {
+    pub opcode: u8,
+    pub argument: u8,
+}
@@ -0,0 +1,150 @@
+use crate::asm::Span;
+use crate::error::{Error, Warning};
+use crate::source::Sources;
+
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A message about a place in the source, ready to be rendered as a rustc style snippet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    /// Further places in the source that are relevant, each with a short explanation.
+    pub notes: Vec<(Span, String)>,
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        let notes = match error {
+            Error::DuplicateLabel { first, .. } => vec![(*first, "first defined here".to_string())],
+            Error::DataOverlap { other, .. } => vec![(*other, "cell already used here".to_string())],
+            Error::SymbolOutOfRange { definition, .. }
+            | Error::MacroArguments { definition, .. } => {
+                vec![(*definition, "defined here".to_string())]
+            }
+            _ => Vec::new(),
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            message: error.message(),
+            span: error.span(),
+            notes,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: warning.message(),
+            span: warning.span(),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic against the files in `sources`.
+    ///
+    /// ```text
+    /// error: label `FOO` is not defined
+    ///  --> example.asm:1:5
+    ///   |
+    /// 1 | JMP FOO
+    ///   |     ^^^
+    /// ```
+    pub fn render(&self, sources: &Sources) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let last_line = self
+            .notes
+            .iter()
+            .map(|(span, _)| span.line)
+            .chain(std::iter::once(self.span.line))
+            .max()
+            .unwrap();
+        let gutter = " ".repeat(last_line.to_string().len());
+
+        let mut result = String::new();
+        writeln!(result, "{}: {}", severity, self.message).unwrap();
+        writeln!(
+            result,
+            "{}--> {}:{}:{}",
+            gutter,
+            sources.file(self.span.file).name,
+            self.span.line,
+            self.span.column
+        )
+        .unwrap();
+        writeln!(result, "{} |", gutter).unwrap();
+        render_snippet(&mut result, &gutter, sources, self.span, "");
+        for (span, note) in self.notes.iter() {
+            // Notes about another file say which one, like rustc does.
+            if span.file != self.span.file {
+                writeln!(
+                    result,
+                    "{}::: {}:{}:{}",
+                    gutter,
+                    sources.file(span.file).name,
+                    span.line,
+                    span.column
+                )
+                .unwrap();
+            }
+            writeln!(result, "{} |", gutter).unwrap();
+            render_snippet(&mut result, &gutter, sources, *span, note);
+        }
+
+        result
+    }
+}
+
+fn render_snippet(result: &mut String, gutter: &str, sources: &Sources, span: Span, label: &str) {
+    let source = sources.file(span.file).content.as_str();
+    let line = source
+        .split('\n')
+        .nth(span.line.saturating_sub(1))
+        .unwrap_or("")
+        .trim_end_matches('\r');
+
+    // Keep tabs so the carets line up with the source no matter the tab width.
+    let indent: String = line
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let underlined = source
+        .get(span.start..span.end.max(span.start))
+        .unwrap_or("")
+        .split('\n')
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('\r')
+        .chars()
+        .count()
+        .max(1);
+
+    writeln!(
+        result,
+        "{:>width$} | {}",
+        span.line,
+        line,
+        width = gutter.len()
+    )
+    .unwrap();
+    write!(result, "{} | {}{}", gutter, indent, "^".repeat(underlined)).unwrap();
+    if !label.is_empty() {
+        write!(result, " {}", label).unwrap();
+    }
+    result.push('\n');
+}
@@ -0,0 +1,175 @@
+use crate::asm::*;
+use crate::generate::Program;
+
+use std::fmt::Write;
+
+/// The names of the labels given to jump and branch targets, one per instruction slot.
+const LABELS: [&str; 16] = [
+    "L0", "L1", "L2", "L3", "L4", "L5", "L6", "L7", "L8", "L9", "LA", "LB", "LC", "LD", "LE", "LF",
+];
+
+/// Turns an assembled program back into instructions.
+///
+/// The program ends with the last slot that does not hold a `NOP`, the data memory cells after it
+/// are data, see [`disassemble_data`]. Jump and branch targets inside the program get a label
+/// named after their address, e.g. `L3`. The unused opcodes 13 to 15 do nothing in the simulator
+/// and become a `NOP`, the operand cells of all `NOP`s are lost, see [`lost_operands`].
+pub fn disassemble(program: &Program) -> Vec<Instruction<'static>> {
+    use ArgumentInstruction::*;
+    use ConstantArgumentInstruction::*;
+
+    let length = program.program_length();
+    let target = |address: usize| -> Option<usize> {
+        let argument = program.data_memory[address] as usize;
+        let target = match program.program_memory[address] {
+            8 => argument,
+            9..=11 => (address + argument) % 16,
+            _ => return None,
+        };
+        Some(target).filter(|target| *target < length)
+    };
+    let mut targets = [false; 16];
+    for address in 0..length {
+        if let Some(target) = target(address) {
+            targets[target] = true;
+        }
+    }
+
+    (0..length)
+        .map(|address| {
+            let span = Span::default();
+            let labels = if targets[address] {
+                vec![Label {
+                    name: LABELS[address],
+                    location: address as u8,
+                    span,
+                }]
+            } else {
+                Vec::new()
+            };
+            let operand = |value| Operand { value, span };
+            let number = operand(Expr::Number(program.data_memory[address] as i32));
+            let location = match target(address) {
+                Some(target) => operand(Expr::Symbol(Operand {
+                    value: LABELS[target],
+                    span,
+                })),
+                None => number.clone(),
+            };
+            let branch = match target(address) {
+                Some(_) => BranchArgument::Target(location.clone()),
+                None => BranchArgument::Offset(number.clone()),
+            };
+
+            match program.program_memory[address] {
+                1 => {
+                    Instruction::ArgumentInstruction(LDA(Argument::Constant(number)), labels, span)
+                }
+                2 => Instruction::ArgumentInstruction(
+                    LDA(Argument::MemoryLocation(number)),
+                    labels,
+                    span,
+                ),
+                3 => Instruction::MemoryLocationInstruction(
+                    MemoryLocationInstruction::STA(number),
+                    labels,
+                    span,
+                ),
+                4 => {
+                    Instruction::ArgumentInstruction(ADD(Argument::Constant(number)), labels, span)
+                }
+                5 => Instruction::ArgumentInstruction(
+                    ADD(Argument::MemoryLocation(number)),
+                    labels,
+                    span,
+                ),
+                6 => {
+                    Instruction::ArgumentInstruction(SUB(Argument::Constant(number)), labels, span)
+                }
+                7 => Instruction::ArgumentInstruction(
+                    SUB(Argument::MemoryLocation(number)),
+                    labels,
+                    span,
+                ),
+                8 => Instruction::Jump(JumpArgument::Location(location), labels, span),
+                9 => Instruction::ConstantArgumentInstruction(BRZ(branch), labels, span),
+                10 => Instruction::ConstantArgumentInstruction(BRC(branch), labels, span),
+                11 => Instruction::ConstantArgumentInstruction(BRN(branch), labels, span),
+                12 => Instruction::Jump(JumpArgument::MemoryLocation(number), labels, span),
+                _ => Instruction::NoArgumentInstruction(NoArgumentInstruction::NOP, labels, span),
+            }
+        })
+        .collect()
+}
+
+/// The data memory cells after the program of [`disassemble`] that are not zero.
+pub fn disassemble_data(program: &Program) -> Vec<DataItem> {
+    (program.program_length()..16)
+        .filter(|address| program.data_memory[*address] != 0)
+        .map(|address| DataItem {
+            address: address as u8,
+            value: Operand {
+                value: program.data_memory[address],
+                span: Span::default(),
+            },
+        })
+        .collect()
+}
+
+/// The operand cells of the `NOP`s in the program of [`disassemble`] that are not zero.
+///
+/// A `NOP` has no operand and the cell can not be initialised with `.byte` either, as it belongs
+/// to the instruction, so these values are missing from the disassembled source.
+pub fn lost_operands(program: &Program) -> Vec<DataItem> {
+    (0..program.program_length())
+        .filter(|address| {
+            let opcode = program.program_memory[*address];
+            (opcode == 0 || opcode > 12) && program.data_memory[*address] != 0
+        })
+        .map(|address| DataItem {
+            address: address as u8,
+            value: Operand {
+                value: program.data_memory[address],
+                span: Span::default(),
+            },
+        })
+        .collect()
+}
+
+/// Prints instructions and data as assembly source, one instruction per line.
+pub fn print_asm(instructions: &[Instruction], data: &[DataItem]) -> String {
+    let mut source = String::new();
+    let labels: Vec<String> = instructions
+        .iter()
+        .map(|instruction| {
+            instruction
+                .labels()
+                .iter()
+                .map(|label| format!("{}: ", label.name))
+                .collect()
+        })
+        .collect();
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+    for (instruction, label) in instructions.iter().zip(labels.iter()) {
+        writeln!(source, "{:width$}{}", label, instruction, width = width).unwrap();
+    }
+
+    // Consecutive cells share one `.byte` directive.
+    let mut next_address = None;
+    for item in data {
+        if next_address != Some(item.address) {
+            if !source.is_empty() {
+                source.push('\n');
+            }
+            write!(source, ".org {}\n.byte {}", item.address, item.value.value).unwrap();
+        } else {
+            write!(source, ", {}", item.value.value).unwrap();
+        }
+        next_address = Some(item.address + 1);
+    }
+    if next_address.is_some() {
+        source.push('\n');
+    }
+
+    source
+}
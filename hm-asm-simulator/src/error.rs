@@ -0,0 +1,251 @@
+use crate::asm::Span;
+use crate::parse::Rule;
+use crate::source::FileId;
+
+use std::fmt;
+
+use pest::error::InputLocation;
+
+/// Everything that can go wrong while parsing, assembling or simulating a program.
+#[derive(Debug)]
+pub enum Error {
+    /// The source `file` does not match the grammar in `asm.pest`.
+    Grammar(Box<pest::error::Error<Rule>>, FileId),
+    /// The program needs more instruction slots than the processor has.
+    TooManyInstructions { count: usize, span: Span },
+    /// A `.byte` directive tries to write past the last data memory cell.
+    DataMemoryFull { span: Span },
+    /// Two things want to initialise the same data memory cell, `other` is the one that came first.
+    DataOverlap { address: u8, span: Span, other: Span },
+    /// An operand refers to a label or constant that is never defined.
+    UndefinedLabel { name: String, span: Span },
+    /// The same label or constant is defined more than once, `first` is where it was
+    /// defined before.
+    DuplicateLabel {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// A branch target lies outside of the 16 instruction slots.
+    UnreachableBranchTarget { target: i32, span: Span },
+    /// An operand does not fit into the four bit operand nibble.
+    OperandOutOfRange { value: i32, span: Span },
+    /// An operand refers to a constant whose value does not fit into the operand nibble.
+    SymbolOutOfRange {
+        name: String,
+        value: u8,
+        span: Span,
+        definition: Span,
+    },
+    /// The value of an `.equ` constant does not fit into a byte.
+    ConstantOutOfRange { value: i32, span: Span },
+    /// A numeric literal could not be converted.
+    InvalidLiteral { literal: String, span: Span },
+    /// A label or constant is named like a mnemonic or a lone hex digit.
+    ReservedName { name: String, span: Span },
+    /// A label on a line of its own with no instruction after it.
+    DanglingLabel { name: String, span: Span },
+    /// An included file could not be loaded, `path` is written as in the `.include`.
+    IncludeFailed {
+        path: String,
+        message: String,
+        span: Span,
+    },
+    /// A file includes itself, directly or through other files.
+    IncludeCycle { path: String, span: Span },
+    /// A line is neither an instruction nor the call of a known macro.
+    UndefinedMacro { name: String, span: Span },
+    /// A macro is called with the wrong number of arguments.
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+        definition: Span,
+    },
+}
+
+impl Error {
+    /// The part of the source this error is about.
+    pub fn span(&self) -> Span {
+        match self {
+            Error::Grammar(e, file) => {
+                let (line, column) = match e.line_col {
+                    pest::error::LineColLocation::Pos(pos) => pos,
+                    pest::error::LineColLocation::Span(start, _) => start,
+                };
+                let (start, end) = match e.location {
+                    InputLocation::Pos(pos) => (pos, pos),
+                    InputLocation::Span(span) => span,
+                };
+                Span {
+                    file: *file,
+                    line,
+                    column,
+                    start,
+                    end,
+                }
+            }
+            Error::TooManyInstructions { span, .. }
+            | Error::DataMemoryFull { span }
+            | Error::DataOverlap { span, .. }
+            | Error::UndefinedLabel { span, .. }
+            | Error::DuplicateLabel { span, .. }
+            | Error::UnreachableBranchTarget { span, .. }
+            | Error::OperandOutOfRange { span, .. }
+            | Error::SymbolOutOfRange { span, .. }
+            | Error::ConstantOutOfRange { span, .. }
+            | Error::InvalidLiteral { span, .. }
+            | Error::ReservedName { span, .. }
+            | Error::DanglingLabel { span, .. }
+            | Error::IncludeFailed { span, .. }
+            | Error::IncludeCycle { span, .. }
+            | Error::UndefinedMacro { span, .. }
+            | Error::MacroArguments { span, .. } => *span,
+        }
+    }
+
+    /// A one line description of the error, without any source location.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Grammar(e, _) => match &e.variant {
+                pest::error::ErrorVariant::ParsingError {
+                    positives,
+                    negatives,
+                } => {
+                    let mut message = String::new();
+                    if !positives.is_empty() {
+                        message.push_str(&format!("expected {}", describe_rules(positives)));
+                    }
+                    if !negatives.is_empty() {
+                        if !message.is_empty() {
+                            message.push_str(", ");
+                        }
+                        message.push_str(&format!("unexpected {}", describe_rules(negatives)));
+                    }
+                    if message.is_empty() {
+                        message.push_str("unknown parsing error");
+                    }
+                    message
+                }
+                pest::error::ErrorVariant::CustomError { message } => message.clone(),
+            },
+            Error::TooManyInstructions { count, .. } => format!(
+                "this program contains {} instructions, but the processor only has room for 16",
+                count
+            ),
+            Error::DataMemoryFull { .. } => {
+                "this value does not fit into the data memory, it only has 16 cells".to_string()
+            }
+            Error::DataOverlap { address, .. } => {
+                format!("data memory cell {:x} is initialised more than once", address)
+            }
+            Error::UndefinedLabel { name, .. } => format!("`{}` is not defined", name),
+            Error::DuplicateLabel { name, .. } => {
+                format!("`{}` is defined more than once", name)
+            }
+            Error::UnreachableBranchTarget { target, .. } => format!(
+                "branch target {} is unreachable, a branch can only reach instructions 0 to 15",
+                target
+            ),
+            Error::OperandOutOfRange { value, .. } => {
+                format!("operand {} does not fit into four bits (-8 to 15)", value)
+            }
+            Error::SymbolOutOfRange { name, value, .. } => format!(
+                "`{}` has the value {}, which does not fit into four bits (0 to 15)",
+                name, value
+            ),
+            Error::ConstantOutOfRange { value, .. } => {
+                format!("constant value {} is out of range (0 to 255)", value)
+            }
+            Error::InvalidLiteral { literal, .. } => format!("`{}` is not a valid number", literal),
+            Error::ReservedName { name, .. } if name.len() == 1 => {
+                format!("`{}` is a hex digit and can not be used as a name", name)
+            }
+            Error::ReservedName { name, .. } => {
+                format!("`{}` is a mnemonic and can not be used as a name", name)
+            }
+            Error::DanglingLabel { name, .. } => {
+                format!("label `{}` is not followed by an instruction", name)
+            }
+            Error::IncludeFailed { path, message, .. } => {
+                format!("could not include `{}`: {}", path, message)
+            }
+            Error::IncludeCycle { path, .. } => {
+                format!("include cycle, `{}` is already being included", path)
+            }
+            Error::UndefinedMacro { name, .. } => {
+                format!("`{}` is neither an instruction nor a macro", name)
+            }
+            Error::MacroArguments {
+                name,
+                expected,
+                found,
+                ..
+            } => format!(
+                "macro `{}` takes {} arguments, but {} were given",
+                name, expected, found
+            ),
+        }
+    }
+}
+
+fn describe_rules(rules: &[Rule]) -> String {
+    let names: Vec<String> = rules
+        .iter()
+        .map(|rule| format!("{:?}", rule).replace('_', " "))
+        .collect();
+    match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(f, "{}:{}: {}", span.line, span.column, self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Something suspicious about a program that still assembles fine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A label that no instruction ever refers to.
+    UnusedLabel { name: String, span: Span },
+    /// A constant that no instruction ever refers to.
+    UnusedConstant { name: String, span: Span },
+}
+
+impl Warning {
+    /// The part of the source this warning is about.
+    pub fn span(&self) -> Span {
+        match self {
+            Warning::UnusedLabel { span, .. } | Warning::UnusedConstant { span, .. } => *span,
+        }
+    }
+
+    /// A one line description of the warning, without any source location.
+    pub fn message(&self) -> String {
+        match self {
+            Warning::UnusedLabel { name, .. } => format!("label `{}` is never used", name),
+            Warning::UnusedConstant { name, .. } => format!("constant `{}` is never used", name),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(f, "{}:{}: {}", span.line, span.column, self.message())
+    }
+}
+
+impl From<pest::error::Error<Rule>> for Error {
+    fn from(e: pest::error::Error<Rule>) -> Self {
+        Error::Grammar(Box::new(e), 0)
+    }
+}
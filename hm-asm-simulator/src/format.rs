@@ -0,0 +1,274 @@
+use crate::error::Error;
+use crate::parse::{AsmParser, Rule};
+
+use std::collections::HashMap;
+
+use pest::iterators::Pair;
+use pest::Parser;
+
+/// How far the lines of a macro body are indented.
+const MACRO_INDENT: &str = "    ";
+
+/// Formats a single source file in the canonical style.
+///
+/// Mnemonics are upper case, directives lower case, operands are written without spaces and
+/// number literals get a canonical spelling, e.g. `0x0C` for `0X0c`. Labels are put in front of
+/// the instruction and all instructions start in the same column. Comments are kept, runs of
+/// blank lines become a single one.
+pub fn format_source(source: &str) -> Result<String, Error> {
+    let pairs =
+        AsmParser::parse(Rule::program, source).map_err(|e| Error::Grammar(Box::new(e), 0))?;
+
+    let mut lines: HashMap<usize, Line> = HashMap::new();
+    let mut macro_lines = Vec::new();
+    for pair in pairs {
+        match pair.as_rule() {
+            Rule::EOI => {}
+            Rule::macro_definition => {
+                let (start, end) = lines_of(&pair);
+                let mut definition = pair.into_inner();
+                let name = definition.next().unwrap().as_str().to_string();
+                let mut parameters = Vec::new();
+                for pair in definition {
+                    match pair.as_rule() {
+                        Rule::label => parameters.push(pair.as_str().to_string()),
+                        _ => {
+                            lines.insert(line_of(&pair), format_line(pair, true));
+                        }
+                    }
+                }
+                let mut header = format!(".macro {}", name);
+                if !parameters.is_empty() {
+                    header.push(' ');
+                    header.push_str(&parameters.join(", "));
+                }
+                lines.insert(start, Line::directive(header));
+                lines.insert(end, Line::directive(".endm".to_string()));
+                macro_lines.push(start + 1..end);
+            }
+            _ => {
+                lines.insert(line_of(&pair), format_line(pair, false));
+            }
+        }
+    }
+    // The instructions of the whole file share one column, those in macro bodies another one.
+    let width = |in_macro: bool| {
+        lines
+            .values()
+            .filter(|line| line.in_macro == in_macro && line.instruction.is_some())
+            .map(|line| line.labels.len())
+            .max()
+            .unwrap_or(0)
+    };
+    let widths = [width(false), width(true)];
+
+    let mut formatted = String::new();
+    let mut blank = false;
+    for (index, text) in source.lines().enumerate() {
+        let number = index + 1;
+        let in_macro = macro_lines.iter().any(|lines| lines.contains(&number));
+        let indent = if in_macro { MACRO_INDENT } else { "" };
+        let mut line = match lines.get(&number) {
+            Some(line) => line.render(widths[line.in_macro as usize]),
+            None => String::new(),
+        };
+        if let Some(comment) = comment(text) {
+            if line.is_empty() {
+                line.push_str(indent);
+            } else {
+                line.push(' ');
+            }
+            line.push_str(comment);
+        }
+
+        if line.is_empty() {
+            blank = !formatted.is_empty();
+            continue;
+        }
+        if blank {
+            formatted.push('\n');
+            blank = false;
+        }
+        formatted.push_str(&line);
+        formatted.push('\n');
+    }
+
+    Ok(formatted)
+}
+
+/// A line with code on it, the labels are already joined, e.g. `LOOP: START:`.
+struct Line {
+    labels: String,
+    /// An instruction or macro call, aligned in the instruction column.
+    instruction: Option<String>,
+    in_macro: bool,
+}
+
+impl Line {
+    fn directive(text: String) -> Self {
+        Line {
+            labels: text,
+            instruction: None,
+            in_macro: false,
+        }
+    }
+
+    fn render(&self, width: usize) -> String {
+        let indent = if self.in_macro { MACRO_INDENT } else { "" };
+        match &self.instruction {
+            Some(instruction) => format!(
+                "{}{:width$}{}",
+                indent,
+                self.labels,
+                instruction,
+                width = width
+            ),
+            None => format!("{}{}", indent, self.labels),
+        }
+    }
+}
+
+fn format_line(pair: Pair<Rule>, in_macro: bool) -> Line {
+    let mut labels = String::new();
+    let mut instruction = None;
+    match pair.as_rule() {
+        Rule::stmt | Rule::label_line | Rule::macro_call => {
+            let mut arguments = Vec::new();
+            let mut name = None;
+            for pair in pair.into_inner() {
+                match pair.as_rule() {
+                    Rule::label => {
+                        labels.push_str(pair.as_str());
+                        labels.push_str(": ");
+                    }
+                    Rule::instruction => instruction = Some(format_instruction(pair)),
+                    Rule::macro_name => name = Some(pair.as_str().to_string()),
+                    _ => arguments.push(format_expr(pair)),
+                }
+            }
+            if let Some(name) = name {
+                instruction = Some(if arguments.is_empty() {
+                    name
+                } else {
+                    format!("{} {}", name, arguments.join(", "))
+                });
+            }
+            // A label line has nothing after its labels.
+            if instruction.is_none() {
+                labels.truncate(labels.trim_end().len());
+            }
+        }
+        Rule::org_directive => {
+            let address = pair.into_inner().next().unwrap();
+            labels = format!(".org {}", format_number(address.as_str()));
+        }
+        Rule::byte_directive => {
+            let values: Vec<String> = pair
+                .into_inner()
+                .map(|value| match value.as_str().strip_prefix('-') {
+                    Some(magnitude) => format!("-{}", format_number(magnitude)),
+                    None => format_number(value.as_str()),
+                })
+                .collect();
+            labels = format!(".byte {}", values.join(", "));
+        }
+        Rule::equ_directive => {
+            let mut equ = pair.into_inner();
+            let name = equ.next().unwrap().as_str();
+            let value = equ.next().unwrap().as_str();
+            labels = format!(".equ {} {}", name, format_number(value));
+        }
+        Rule::include_directive => {
+            let path = pair.into_inner().next().unwrap().as_str();
+            labels = format!(".include \"{}\"", path);
+        }
+        _ => unreachable!(),
+    }
+
+    Line {
+        labels,
+        instruction,
+        in_macro,
+    }
+}
+
+fn format_instruction(instruction: Pair<Rule>) -> String {
+    let mut instruction = instruction.into_inner();
+    let mnemonic = instruction.next().unwrap().as_str().to_ascii_uppercase();
+    match instruction.next() {
+        Some(argument) => format!("{} {}", mnemonic, format_argument(argument)),
+        None => mnemonic,
+    }
+}
+
+fn format_argument(argument: Pair<Rule>) -> String {
+    match argument.as_rule() {
+        Rule::argument | Rule::jump_argument | Rule::branch_argument => {
+            format_argument(argument.into_inner().next().unwrap())
+        }
+        Rule::memory_location => {
+            format!("({})", format_expr(argument.into_inner().next().unwrap()))
+        }
+        Rule::digit_literal => format!("#{}", format_expr(argument.into_inner().next().unwrap())),
+        _ => format_expr(argument),
+    }
+}
+
+fn format_expr(expr: Pair<Rule>) -> String {
+    match expr.as_rule() {
+        Rule::expr | Rule::jump_expr => expr
+            .into_inner()
+            .map(|term| match term.as_rule() {
+                // A nested expression was written in parentheses.
+                Rule::expr => format!("({})", format_expr(term)),
+                _ => format_expr(term),
+            })
+            .collect(),
+        Rule::negation | Rule::jump_negation => {
+            let term = expr.into_inner().next().unwrap();
+            match term.as_rule() {
+                Rule::expr => format!("-({})", format_expr(term)),
+                _ => format!("-{}", format_expr(term)),
+            }
+        }
+        Rule::number => format_number(expr.as_str()),
+        _ => expr.as_str().to_string(),
+    }
+}
+
+/// Lower case prefixes and upper case hex digits, decimal numbers stay as they are.
+fn format_number(literal: &str) -> String {
+    let lower = literal.to_ascii_lowercase();
+    if let Some(digits) = lower.strip_prefix("0x") {
+        format!("0x{}", digits.to_ascii_uppercase())
+    } else if lower.starts_with("0b") {
+        lower
+    } else {
+        literal.to_ascii_uppercase()
+    }
+}
+
+/// The `//` comment at the end of `line`, if there is one outside of an `.include` path.
+fn comment(line: &str) -> Option<&str> {
+    let mut quoted = false;
+    let bytes = line.as_bytes();
+    for (index, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            b'/' if !quoted && bytes.get(index + 1) == Some(&b'/') => {
+                return Some(line[index..].trim_end())
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn line_of(pair: &Pair<Rule>) -> usize {
+    pair.as_span().start_pos().line_col().0
+}
+
+/// The first and the last line of a pair that spans several lines.
+fn lines_of(pair: &Pair<Rule>) -> (usize, usize) {
+    (line_of(pair), pair.as_span().end_pos().line_col().0)
+}
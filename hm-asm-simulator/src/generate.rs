@@ -1,5 +1,6 @@
 use crate::asm::*;
-use std::collections::HashMap;
+use crate::error::{Error, Warning};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 use serde::Serialize;
@@ -33,66 +34,372 @@ impl fmt::Display for Program {
     }
 }
 
-pub fn generate_binary(instructions: Vec<Instruction>) -> Program {
-    let mut labels: HashMap<&str, u8> = HashMap::new();
-    let mut data_memory: [u8; 16] = [0; 16];
-    let mut program_memory: [u8; 16] = [0; 16];
+/// One of the two memories of a [`Program`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Memory {
+    Data,
+    Program,
+}
+
+impl Program {
+    pub fn memory(&self, memory: Memory) -> &[u8; 16] {
+        match memory {
+            Memory::Data => &self.data_memory,
+            Memory::Program => &self.program_memory,
+        }
+    }
+
+    /// The number of instruction slots up to and including the last one that is not a `NOP`.
+    pub fn program_length(&self) -> usize {
+        self.program_memory
+            .iter()
+            .rposition(|opcode| *opcode != 0)
+            .map_or(0, |last| last + 1)
+    }
+
+    /// The memory as Intel HEX, a single data record with one byte per cell and an end of file record.
+    pub fn intel_hex(&self, memory: Memory) -> String {
+        let cells = self.memory(memory);
+        // Byte count, 16 bit address and record type 0 for data.
+        let mut record = vec![cells.len() as u8, 0, 0, 0];
+        record.extend_from_slice(cells);
+        let checksum = record
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        record.push(checksum);
+
+        let mut hex = String::from(":");
+        for byte in record {
+            hex.push_str(&format!("{:02X}", byte));
+        }
+        hex.push_str("\n:00000001FF\n");
+        hex
+    }
+
+    /// The memory as raw binary with one byte per cell.
+    pub fn raw(&self, memory: Memory) -> Vec<u8> {
+        self.memory(memory).to_vec()
+    }
+
+    /// The memory as raw binary with two cells per byte, the first one in the high nibble.
+    pub fn packed(&self, memory: Memory) -> Vec<u8> {
+        self.memory(memory)
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect()
+    }
+
+    /// The memory as a Logisim `v2.0 raw` image that a ROM or RAM component can load.
+    pub fn logisim(&self, memory: Memory) -> String {
+        let mut image = String::from("v2.0 raw\n");
+        for chunk in self.memory(memory).chunks(4) {
+            let line: Vec<String> = chunk.iter().map(|cell| format!("{:x}", cell)).collect();
+            image.push_str(&line.join(" "));
+            image.push('\n');
+        }
+        image
+    }
+
+    /// The memory as a `$readmemh` file, one cell per line.
+    pub fn readmemh(&self, memory: Memory) -> String {
+        self.memory(memory)
+            .iter()
+            .map(|cell| format!("{:x}\n", cell))
+            .collect()
+    }
+
+    /// A VHDL package called `name` with both memories as `PROGRAM_MEMORY` and `DATA_MEMORY`
+    /// constants of the type `memory_t`.
+    pub fn vhdl_package(&self, name: &str) -> String {
+        let constant = |cells: &[u8; 16]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .map(|cell| format!("x\"{:x}\"", cell))
+                .collect();
+            cells.join(", ")
+        };
+        let mut package = String::new();
+        package.push_str("library ieee;\nuse ieee.std_logic_1164.all;\n\n");
+        package.push_str(&format!("package {} is\n", name));
+        package
+            .push_str("    type memory_t is array (0 to 15) of std_logic_vector(3 downto 0);\n\n");
+        package.push_str(&format!(
+            "    constant PROGRAM_MEMORY : memory_t := ({});\n",
+            constant(&self.program_memory)
+        ));
+        package.push_str(&format!(
+            "    constant DATA_MEMORY : memory_t := ({});\n",
+            constant(&self.data_memory)
+        ));
+        package.push_str(&format!("end package {};\n", name));
+        package
+    }
+
+    /// A Verilog module called `name` that looks up the opcode and the initial operand at an
+    /// address in a `case` ROM.
+    pub fn verilog_rom(&self, name: &str) -> String {
+        let mut module = format!("module {} (\n", name);
+        module.push_str("    input  wire [3:0] address,\n");
+        module.push_str("    output reg  [3:0] opcode,\n");
+        module.push_str("    output reg  [3:0] operand\n");
+        module.push_str(");\n");
+        module.push_str("    always @(*) begin\n");
+        module.push_str("        case (address)\n");
+        for (address, (opcode, operand)) in self
+            .program_memory
+            .iter()
+            .zip(self.data_memory.iter())
+            .enumerate()
+        {
+            module.push_str(&format!(
+                "            4'h{:x}: begin opcode = 4'h{:x}; operand = 4'h{:x}; end\n",
+                address, opcode, operand
+            ));
+        }
+        module.push_str("        endcase\n");
+        module.push_str("    end\n");
+        module.push_str("endmodule\n");
+        module
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+}
+
+/// A name that operands can refer to, either a label or a constant defined with `.equ`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub value: u8,
+    pub kind: SymbolKind,
+    pub span: Span,
+}
+
+/// All labels and constants of a program, shared by every operand that refers to a name.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable<'a> {
+    symbols: HashMap<&'a str, Symbol<'a>>,
+    used: HashSet<&'a str>,
+}
+
+impl<'a> SymbolTable<'a> {
+    /// Collects the labels and constants of `ast`, rejecting names that are defined twice.
+    pub fn new(ast: &Ast<'a>) -> Result<Self, Error> {
+        let mut table = SymbolTable::default();
+        for instruction in ast.instructions.iter() {
+            for label in instruction.labels() {
+                table.insert(Symbol {
+                    name: label.name,
+                    value: label.location,
+                    kind: SymbolKind::Label,
+                    span: label.span,
+                })?;
+            }
+        }
+        for constant in ast.constants.iter() {
+            table.insert(Symbol {
+                name: constant.name,
+                value: constant.value.value,
+                kind: SymbolKind::Constant,
+                span: constant.span,
+            })?;
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, symbol: Symbol<'a>) -> Result<(), Error> {
+        if let Some(first) = self.symbols.insert(symbol.name, symbol) {
+            return Err(Error::DuplicateLabel {
+                name: symbol.name.to_string(),
+                span: symbol.span,
+                first: first.span,
+            });
+        }
+        Ok(())
+    }
+
+    /// All symbols, in no particular order.
+    pub fn symbols(&self) -> impl Iterator<Item = &Symbol<'a>> {
+        self.symbols.values()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol<'a>> {
+        self.symbols.get(name)
+    }
 
-    // collect all labels
-    for instruction in instructions.iter() {
-        match instruction {
-            Instruction::NoArgumentInstruction(_, label)
-            | Instruction::MemoryLocationInstruction(_, label)
-            | Instruction::ConstantArgumentInstruction(_, label)
-            | Instruction::ArgumentInstruction(_, label)
-            | Instruction::Jump(_, label) => {
-                insert_label(&mut labels, label);
+    /// Looks up the symbol `name` refers to and marks it as used.
+    pub fn resolve(&mut self, name: Operand<&'a str>) -> Result<Symbol<'a>, Error> {
+        match self.symbols.get(name.value) {
+            Some(symbol) => {
+                self.used.insert(symbol.name);
+                Ok(*symbol)
             }
+            None => Err(Error::UndefinedLabel {
+                name: name.value.to_string(),
+                span: name.span,
+            }),
         }
     }
 
+    /// Evaluates a constant expression.
+    pub fn evaluate(&mut self, expr: &Expr<'a>) -> Result<i32, Error> {
+        Ok(match expr {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => self.resolve(*name)?.value as i32,
+            Expr::Neg(expr) => self.evaluate(expr)?.wrapping_neg(),
+            Expr::Add(lhs, rhs) => self.evaluate(lhs)?.wrapping_add(self.evaluate(rhs)?),
+            Expr::Sub(lhs, rhs) => self.evaluate(lhs)?.wrapping_sub(self.evaluate(rhs)?),
+        })
+    }
+
+    /// Computes the value of an operand, which has to fit into the four bit operand nibble.
+    ///
+    /// Negative values down to -8 are stored in two's complement.
+    pub fn value(&mut self, operand: &Operand<Expr<'a>>) -> Result<u8, Error> {
+        let value = self.evaluate(&operand.value)?;
+        if !(-8..=15).contains(&value) {
+            if let Expr::Symbol(name) = operand.value {
+                let symbol = self.symbols[name.value];
+                return Err(Error::SymbolOutOfRange {
+                    name: name.value.to_string(),
+                    value: symbol.value,
+                    span: operand.span,
+                    definition: symbol.span,
+                });
+            }
+            return Err(Error::OperandOutOfRange {
+                value,
+                span: operand.span,
+            });
+        }
+        Ok((value & 0b1111) as u8)
+    }
+
+    /// Warnings for all symbols that were never resolved, in source order.
+    pub fn unused(&self) -> Vec<Warning> {
+        let mut unused: Vec<&Symbol> = self
+            .symbols
+            .values()
+            .filter(|symbol| !self.used.contains(symbol.name))
+            .collect();
+        unused.sort_by_key(|symbol| symbol.span.start);
+        unused
+            .into_iter()
+            .map(|symbol| match symbol.kind {
+                SymbolKind::Label => Warning::UnusedLabel {
+                    name: symbol.name.to_string(),
+                    span: symbol.span,
+                },
+                SymbolKind::Constant => Warning::UnusedConstant {
+                    name: symbol.name.to_string(),
+                    span: symbol.span,
+                },
+            })
+            .collect()
+    }
+}
+
+/// Assembles `ast` into a `Program`, together with any warnings about the source.
+pub fn generate_binary(ast: &Ast) -> Result<(Program, Vec<Warning>), Error> {
+    let instructions = &ast.instructions;
+    if instructions.len() > 16 {
+        return Err(Error::TooManyInstructions {
+            count: instructions.len(),
+            span: instructions[16].span(),
+        });
+    }
+
+    let mut symbols = SymbolTable::new(ast)?;
+    let mut data_memory: [u8; 16] = [0; 16];
+    let mut program_memory: [u8; 16] = [0; 16];
+    // What initialised each data memory cell, to report overlapping data.
+    let mut data_owners: [Option<Span>; 16] = [None; 16];
+
     for (c, instruction) in instructions.iter().enumerate() {
         let binary_instruction: BinaryInstruction = match instruction {
-            Instruction::NoArgumentInstruction(instruction, _) => (*instruction).into(),
-            Instruction::MemoryLocationInstruction(instruction, _) => (*instruction).into(),
-            Instruction::ConstantArgumentInstruction(instruction, _) => (*instruction).into(),
-            Instruction::ArgumentInstruction(instruction, _) => (*instruction).into(),
-            Instruction::Jump(argument, _) => match argument {
-                JumpArgument::Location(arg) => BinaryInstruction {
+            Instruction::NoArgumentInstruction(instruction, _, _) => BinaryInstruction {
+                opcode: instruction.opcode(),
+                argument: 0,
+            },
+            Instruction::MemoryLocationInstruction(instruction, _, _) => BinaryInstruction {
+                opcode: instruction.opcode(),
+                argument: symbols.value(instruction.argument())?,
+            },
+            Instruction::ConstantArgumentInstruction(instruction, _, _) => BinaryInstruction {
+                opcode: instruction.opcode(),
+                argument: match instruction.argument() {
+                    BranchArgument::Offset(offset) => symbols.value(offset)?,
+                    BranchArgument::Target(target) => {
+                        let address = symbols.evaluate(&target.value)?;
+                        branch_offset(c as u8, address, target.span)?
+                    }
+                },
+            },
+            Instruction::ArgumentInstruction(instruction, _, _) => BinaryInstruction {
+                opcode: instruction.opcode(),
+                argument: symbols.value(instruction.argument())?,
+            },
+            Instruction::Jump(argument, _, _) => match argument {
+                JumpArgument::Location(location) => BinaryInstruction {
                     opcode: 8,
-                    argument: *arg,
+                    argument: symbols.value(location)?,
                 },
-                JumpArgument::Label(arg) => {
-                    if let Some(address) = labels.get(*arg) {
-                        BinaryInstruction {
-                            opcode: 8,
-                            argument: *address,
-                        }
-                    } else {
-                        panic!("Tried to JMP to label: {}, which does not exist", arg);
-                    }
+                JumpArgument::MemoryLocation(address) => BinaryInstruction {
+                    opcode: 12,
+                    argument: symbols.value(address)?,
+                },
+                JumpArgument::Offset(offset) => BinaryInstruction {
+                    opcode: 8,
+                    argument: (c as u8 + symbols.value(offset)?) % 16,
                 },
-                JumpArgument::MemoryLocation(address) => {
-                    BinaryInstruction {
-                        opcode: 12,
-                        argument: *address
-                    }
-                }
             },
         };
 
         program_memory[c] = binary_instruction.opcode;
         data_memory[c] = binary_instruction.argument;
+        data_owners[c] = Some(instruction.span());
     }
 
-    Program {
-        data_memory,
-        program_memory,
+    for item in ast.data.iter() {
+        let address = item.address as usize;
+        if let Some(other) = data_owners[address] {
+            return Err(Error::DataOverlap {
+                address: item.address,
+                span: item.value.span,
+                other,
+            });
+        }
+        if item.value.value > 15 {
+            return Err(Error::OperandOutOfRange {
+                value: item.value.value as i32,
+                span: item.value.span,
+            });
+        }
+
+        data_memory[address] = item.value.value;
+        data_owners[address] = Some(item.value.span);
     }
+
+    Ok((
+        Program {
+            data_memory,
+            program_memory,
+        },
+        symbols.unused(),
+    ))
 }
 
-pub fn insert_label<'a>(hashmap: &mut HashMap<&'a str, u8>, label: &Option<Label<'a>>) {
-    if let Some(label) = label {
-        hashmap.insert(label.name, label.location);
+/// Computes the operand a branch at `from` needs to reach `to`.
+///
+/// The simulator adds the operand to the program counter modulo 16, so backward branches
+/// wrap around, but the target itself has to be one of the 16 instruction slots.
+fn branch_offset(from: u8, to: i32, span: Span) -> Result<u8, Error> {
+    if !(0..=15).contains(&to) {
+        return Err(Error::UnreachableBranchTarget { target: to, span });
     }
+    Ok(((to + 16 - from as i32) % 16) as u8)
 }
@@ -3,5 +3,13 @@ extern crate pest_derive;
 
 pub mod generate;
 pub mod asm;
+pub mod diagnostics;
+pub mod disassemble;
+pub mod error;
+pub mod format;
+pub mod listing;
 pub mod parse;
 pub mod simulate;
+pub mod source;
+
+pub use error::{Error, Warning};
@@ -0,0 +1,128 @@
+use crate::asm::*;
+use crate::error::Error;
+use crate::generate::{Program, SymbolKind, SymbolTable};
+use crate::source::Sources;
+
+use std::fmt::Write;
+
+/// Prints an assembly listing of `ast`, which was assembled into `program`.
+///
+/// Every instruction and data cell gets a line with its address, the opcode and operand nibble
+/// at that address, its labels, the instruction as it was assembled and the source line it came
+/// from. Pseudo-instructions show their expansion next to the original line, instructions from a
+/// macro the line of the macro body. The listing ends with a table of all labels and constants.
+pub fn listing(ast: &Ast, program: &Program, sources: &Sources) -> Result<String, Error> {
+    let mut rows = Vec::new();
+    for (address, instruction) in ast.instructions.iter().enumerate() {
+        let labels: String = instruction
+            .labels()
+            .iter()
+            .map(|label| format!("{}: ", label.name))
+            .collect();
+        rows.push(Row {
+            address,
+            labels,
+            instruction: instruction.to_string(),
+            span: instruction.span(),
+        });
+    }
+    for item in ast.data.iter() {
+        rows.push(Row {
+            address: item.address as usize,
+            labels: String::new(),
+            instruction: format!(".byte {}", item.value.value),
+            span: item.value.span,
+        });
+    }
+
+    let label_width = column_width(rows.iter().map(|row| row.labels.len()), "Labels");
+    let instruction_width =
+        column_width(rows.iter().map(|row| row.instruction.len()), "Instruction");
+    let locations: Vec<String> = rows
+        .iter()
+        .map(|row| format!("{}:{}", sources.file(row.span.file).name, row.span.line))
+        .collect();
+    let location_width = column_width(locations.iter().map(String::len), "Line");
+
+    let mut listing = String::new();
+    writeln!(
+        listing,
+        "{:<4} {:<2} {:<3} {:lw$}{:iw$}  {:sw$}  Source",
+        "Addr",
+        "Op",
+        "Arg",
+        "Labels",
+        "Instruction",
+        "Line",
+        lw = label_width,
+        iw = instruction_width,
+        sw = location_width
+    )
+    .unwrap();
+    for (row, location) in rows.iter().zip(locations.iter()) {
+        let source = source_line(sources, row.span);
+        let line = format!(
+            "{:<4X} {:<2X} {:<3X} {:lw$}{:iw$}  {:sw$}  {}",
+            row.address,
+            program.program_memory[row.address],
+            program.data_memory[row.address],
+            row.labels,
+            row.instruction,
+            location,
+            source,
+            lw = label_width,
+            iw = instruction_width,
+            sw = location_width
+        );
+        writeln!(listing, "{}", line.trim_end()).unwrap();
+    }
+
+    let table = SymbolTable::new(ast)?;
+    let mut symbols: Vec<_> = table.symbols().collect();
+    if !symbols.is_empty() {
+        symbols.sort_by_key(|symbol| symbol.name);
+        let name_width = column_width(symbols.iter().map(|symbol| symbol.name.len()), "Symbol");
+        writeln!(listing, "\n{:nw$}  Value  Kind", "Symbol", nw = name_width).unwrap();
+        for symbol in symbols {
+            let kind = match symbol.kind {
+                SymbolKind::Label => "label",
+                SymbolKind::Constant => "constant",
+            };
+            writeln!(
+                listing,
+                "{:nw$}  {:<5}  {}",
+                symbol.name,
+                symbol.value,
+                kind,
+                nw = name_width
+            )
+            .unwrap();
+        }
+    }
+
+    Ok(listing)
+}
+
+/// A line of the listing, either an instruction or a data cell.
+struct Row {
+    address: usize,
+    labels: String,
+    instruction: String,
+    span: Span,
+}
+
+/// The width of a column holding the given lengths and its heading, with room for a space.
+fn column_width(lengths: impl Iterator<Item = usize>, heading: &str) -> usize {
+    lengths.max().unwrap_or(0).max(heading.len()) + 1
+}
+
+/// The whole line `span` starts on, comments included.
+fn source_line(sources: &Sources, span: Span) -> &str {
+    sources
+        .file(span.file)
+        .content
+        .lines()
+        .nth(span.line - 1)
+        .unwrap_or("")
+        .trim()
+}
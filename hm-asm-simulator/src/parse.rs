@@ -1,74 +1,423 @@
 use crate::asm::*;
+use crate::error::Error;
+use crate::source::{FileId, Sources};
 use pest::iterators::{Pair, Pairs};
+use pest::Parser;
+
+use std::collections::{HashMap, HashSet};
 
 #[derive(Parser)]
 #[grammar = "asm.pest"]
 pub struct AsmParser;
 
-pub fn parse_asm<'a>(pairs: Pairs<'a, Rule>) -> Vec<Instruction<'a>> {
-    let mut instruction = Vec::new();
-    let mut instruction_counter = 0;
-    for stmnt in pairs {
-        if let Rule::stmt = stmnt.as_rule() {
-            let mut stmnt = stmnt.into_inner();
-            let first = stmnt.next();
-            let second = stmnt.next();
-
-            // Second can only be Some if we have a label, thus second must be the
-            // instruction if that is the case.
-            if second.is_some() {
-                instruction.push(parse_instruction(
-                    second.unwrap(),
-                    first,
-                    instruction_counter,
-                ));
-            } else {
-                instruction.push(parse_instruction(first.unwrap(), None, instruction_counter));
+/// Parses the first file of `sources`, with the content of every included file in place of
+/// its `.include`. The included files have to be loaded with `Sources::load_includes` first.
+pub fn parse_asm<'a>(sources: &'a Sources) -> Result<Ast<'a>, Error> {
+    let files = sources
+        .files()
+        .map(|(file, source)| {
+            AsmParser::parse(Rule::program, &source.content)
+                .map_err(|e| Error::Grammar(Box::new(e), file))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Macros can be used before they are defined, even in another file, so collect them first.
+    let mut macros: HashMap<&str, Macro> = HashMap::new();
+    for (file, pairs) in files.iter().enumerate() {
+        for definition in pairs.clone() {
+            if definition.as_rule() == Rule::macro_definition {
+                let definition = parse_macro_definition(definition, file)?;
+                if let Some(first) = macros.get(definition.name.value) {
+                    return Err(Error::DuplicateLabel {
+                        name: definition.name.value.to_string(),
+                        span: definition.name.span,
+                        first: first.name.span,
+                    });
+                }
+                macros.insert(definition.name.value, definition);
+            }
+        }
+    }
+
+    let mut program = ProgramBuilder {
+        sources,
+        files: &files,
+        macros: &macros,
+        included: HashSet::new(),
+        instructions: Vec::new(),
+        data: Vec::new(),
+        constants: Vec::new(),
+        data_counter: 0,
+        labels: Vec::new(),
+    };
+    program.included.insert(0);
+    program.parse_file(0)?;
+
+    if let Some((label, file)) = program.labels.first() {
+        return Err(Error::DanglingLabel {
+            name: label.as_str().to_string(),
+            span: Span::new(label.as_span(), *file),
+        });
+    }
+
+    if program.instructions.len() > 16 {
+        return Err(Error::TooManyInstructions {
+            count: program.instructions.len(),
+            span: program.instructions[16].span(),
+        });
+    }
+
+    Ok(Ast {
+        instructions: program.instructions,
+        data: program.data,
+        constants: program.constants,
+    })
+}
+
+/// The state of `parse_asm` while it walks through the files of a program.
+struct ProgramBuilder<'a, 'p> {
+    sources: &'a Sources,
+    files: &'p [Pairs<'a, Rule>],
+    macros: &'p HashMap<&'a str, Macro<'a>>,
+    /// Every file is only included once, no matter how many files include it.
+    included: HashSet<FileId>,
+    instructions: Vec<Instruction<'a>>,
+    data: Vec<DataItem>,
+    constants: Vec<Constant<'a>>,
+    data_counter: usize,
+    /// Labels seen on lines of their own, waiting for the next instruction.
+    labels: Vec<(Pair<'a, Rule>, FileId)>,
+}
+
+impl<'a, 'p> ProgramBuilder<'a, 'p> {
+    fn parse_file(&mut self, file: FileId) -> Result<(), Error> {
+        for stmnt in self.files[file].clone() {
+            match stmnt.as_rule() {
+                Rule::stmt => {
+                    // The instruction always comes last, everything before it is a label.
+                    let mut stmnt: Vec<_> = stmnt.into_inner().collect();
+                    let mnemonic = stmnt.pop().unwrap();
+                    self.labels
+                        .extend(stmnt.into_iter().map(|label| (label, file)));
+                    let instruction = parse_instruction(
+                        mnemonic,
+                        self.labels.drain(..),
+                        self.instructions.len() as u8,
+                        file,
+                    )?;
+                    self.instructions.push(instruction);
+                }
+                Rule::label_line => self
+                    .labels
+                    .extend(stmnt.into_inner().map(|label| (label, file))),
+                Rule::macro_call => self.expand_macro(stmnt, file)?,
+                Rule::include_directive => {
+                    let offset = stmnt.as_span().start();
+                    let path = stmnt.into_inner().next().unwrap();
+                    // `Sources::load_includes` has to run before the files can be parsed.
+                    let included = self.sources.included(file, offset).ok_or_else(|| {
+                        Error::IncludeFailed {
+                            path: path.as_str().to_string(),
+                            message: "the file was not loaded".to_string(),
+                            span: Span::new(path.as_span(), file),
+                        }
+                    })?;
+                    if self.included.insert(included) {
+                        self.parse_file(included)?;
+                    }
+                }
+                Rule::org_directive => {
+                    let address = stmnt.into_inner().next().unwrap();
+                    let span = Span::new(address.as_span(), file);
+                    self.data_counter = match parse_number(address.as_str(), span)? {
+                        address @ 0..=15 => address as usize,
+                        _ => return Err(Error::DataMemoryFull { span }),
+                    };
+                }
+                Rule::byte_directive => {
+                    for value in stmnt.into_inner() {
+                        let value = parse_data_value(value, file)?;
+                        if self.data_counter > 15 {
+                            return Err(Error::DataMemoryFull { span: value.span });
+                        }
+                        self.data.push(DataItem {
+                            address: self.data_counter as u8,
+                            value,
+                        });
+                        self.data_counter += 1;
+                    }
+                }
+                Rule::equ_directive => {
+                    let mut equ = stmnt.into_inner();
+                    let name = equ.next().unwrap();
+                    let value = equ.next().unwrap();
+                    let value_span = Span::new(value.as_span(), file);
+                    let value = match parse_number(value.as_str(), value_span)? {
+                        value @ 0..=255 => value as u8,
+                        value => {
+                            return Err(Error::ConstantOutOfRange {
+                                value,
+                                span: value_span,
+                            })
+                        }
+                    };
+                    self.constants.push(Constant {
+                        name: parse_name(&name, file)?,
+                        value: Operand {
+                            value,
+                            span: value_span,
+                        },
+                        span: Span::new(name.as_span(), file),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the instructions of a macro call, the labels in front of the call and those
+    /// waiting in `labels` point at the first of them.
+    fn expand_macro(&mut self, call: Pair<'a, Rule>, file: FileId) -> Result<(), Error> {
+        let mut call: Vec<_> = call.into_inner().collect();
+        let position = call
+            .iter()
+            .position(|pair| pair.as_rule() == Rule::macro_name)
+            .unwrap();
+        let arguments = call.split_off(position + 1);
+        let name = call.pop().unwrap();
+        self.labels
+            .extend(call.into_iter().map(|label| (label, file)));
+
+        let span = Span::new(name.as_span(), file);
+        let definition = self
+            .macros
+            .get(name.as_str())
+            .ok_or_else(|| Error::UndefinedMacro {
+                name: name.as_str().to_string(),
+                span,
+            })?;
+        if arguments.len() != definition.parameters.len() {
+            return Err(Error::MacroArguments {
+                name: name.as_str().to_string(),
+                expected: definition.parameters.len(),
+                found: arguments.len(),
+                span,
+                definition: definition.name.span,
+            });
+        }
+
+        let mut substitutions = HashMap::new();
+        for (parameter, argument) in definition.parameters.iter().zip(arguments) {
+            substitutions.insert(parameter.value, parse_expr(argument, file)?);
+        }
+        // Local labels are replaced by the address they get in this expansion.
+        let mut address = self.instructions.len() as i32;
+        for line in definition.body.iter() {
+            for label in line.clone().into_inner() {
+                if label.as_rule() == Rule::label {
+                    substitutions.insert(label.as_str(), Expr::Number(address));
+                }
+            }
+            if line.as_rule() == Rule::stmt {
+                address += 1;
+            }
+        }
+
+        for line in definition.body.iter() {
+            if line.as_rule() != Rule::stmt {
+                continue;
+            }
+            let mnemonic = line.clone().into_inner().last().unwrap();
+            let mut instruction = parse_instruction(
+                mnemonic,
+                self.labels.drain(..),
+                self.instructions.len() as u8,
+                definition.name.span.file,
+            )?;
+            if let Some(operand) = instruction.operand_mut() {
+                substitute(&mut operand.value, &substitutions);
             }
+            self.instructions.push(instruction);
+        }
+
+        Ok(())
+    }
+}
+
+/// A `.macro` definition, its body is parsed anew for every expansion.
+struct Macro<'a> {
+    name: Operand<&'a str>,
+    parameters: Vec<Operand<&'a str>>,
+    /// The `stmt` and `label_line` pairs between `.macro` and `.endm`.
+    body: Vec<Pair<'a, Rule>>,
+}
+
+fn parse_macro_definition<'a>(
+    definition: Pair<'a, Rule>,
+    file: FileId,
+) -> Result<Macro<'a>, Error> {
+    let mut definition = definition.into_inner();
+    let name = definition.next().unwrap().into_inner().next().unwrap();
+    let name = Operand {
+        value: parse_name(&name, file)?,
+        span: Span::new(name.as_span(), file),
+    };
 
-            instruction_counter += 1;
+    let mut parameters = Vec::new();
+    let mut body = Vec::new();
+    for pair in definition {
+        match pair.as_rule() {
+            Rule::label => parameters.push(pair),
+            _ => body.push(pair),
         }
+    }
 
-        if instruction_counter > 16 {
-            panic!("This program contains more than 16 instructions, that is impossible on this processor");
+    // Parameters and local labels share one namespace for the expansion.
+    let mut names: HashMap<&str, Span> = HashMap::new();
+    let local_labels = body
+        .iter()
+        .flat_map(|line| line.clone().into_inner())
+        .filter(|pair| pair.as_rule() == Rule::label);
+    for pair in parameters.iter().cloned().chain(local_labels) {
+        let span = Span::new(pair.as_span(), file);
+        let name = parse_name(&pair, file)?;
+        if let Some(first) = names.insert(name, span) {
+            return Err(Error::DuplicateLabel {
+                name: name.to_string(),
+                span,
+                first,
+            });
+        }
+    }
+
+    Ok(Macro {
+        name,
+        parameters: parameters
+            .iter()
+            .map(|parameter| Operand {
+                value: parameter.as_str(),
+                span: Span::new(parameter.as_span(), file),
+            })
+            .collect(),
+        body,
+    })
+}
+
+/// Replaces the macro parameters and local labels in `expr`.
+fn substitute<'a>(expr: &mut Expr<'a>, substitutions: &HashMap<&'a str, Expr<'a>>) {
+    match expr {
+        Expr::Number(_) => {}
+        Expr::Symbol(name) => {
+            if let Some(value) = substitutions.get(name.value) {
+                *expr = value.clone();
+            }
+        }
+        Expr::Neg(inner) => substitute(inner, substitutions),
+        Expr::Add(lhs, rhs) | Expr::Sub(lhs, rhs) => {
+            substitute(lhs, substitutions);
+            substitute(rhs, substitutions);
         }
     }
-    instruction
 }
 
 fn parse_instruction<'a>(
     instruction: Pair<'a, Rule>,
-    label: Option<Pair<'a, Rule>>,
+    labels: impl Iterator<Item = (Pair<'a, Rule>, FileId)>,
     instruction_counter: u8,
-) -> Instruction<'a> {
-    let label = label.map(|l| parse_label(l, instruction_counter));
+    file: FileId,
+) -> Result<Instruction<'a>, Error> {
+    let labels = labels
+        .map(|(label, file)| parse_label(label, instruction_counter, file))
+        .collect::<Result<Vec<_>, _>>()?;
+    let span = Span::new(instruction.as_span(), file);
     let mut instruction = instruction.into_inner();
     let mnemonic = instruction.next().unwrap();
 
     match mnemonic.as_rule() {
-        Rule::no_arg_instruction => parse_no_arg_instruction(mnemonic, label),
+        Rule::no_arg_instruction => Ok(parse_no_arg_instruction(mnemonic, labels, span)),
         Rule::arg_instruction => {
-            parse_arg_instruction(mnemonic, instruction.next().unwrap(), label)
+            parse_arg_instruction(mnemonic, instruction.next().unwrap(), labels, span)
         }
         Rule::jump_instruction => {
-            parse_jump_instruction(mnemonic, instruction.next().unwrap(), label)
+            parse_jump_instruction(mnemonic, instruction.next().unwrap(), labels, span)
         }
         Rule::memory_location_instruction => {
-            parse_memory_location_instruction(mnemonic, instruction.next().unwrap(), label)
+            parse_memory_location_instruction(mnemonic, instruction.next().unwrap(), labels, span)
         }
         Rule::constant_arg_instruction => {
-            parse_constant_arg_instruction(mnemonic, instruction.next().unwrap(), label)
+            parse_constant_arg_instruction(mnemonic, instruction.next().unwrap(), labels, span)
+        }
+        Rule::pseudo_instruction => Ok(parse_pseudo_instruction(
+            mnemonic,
+            labels,
+            span,
+            instruction_counter,
+        )),
+        Rule::pseudo_branch_instruction => {
+            parse_pseudo_branch_instruction(instruction.next().unwrap(), labels, span)
         }
         _ => unreachable!(),
     }
 }
 
+/// Expands `CLR`, `INC`, `DEC` and `HLT` into the instruction they stand for.
+fn parse_pseudo_instruction<'a>(
+    instruction: Pair<'a, Rule>,
+    labels: Vec<Label<'a>>,
+    span: Span,
+    instruction_counter: u8,
+) -> Instruction<'a> {
+    let constant = |value| {
+        Argument::Constant(Operand {
+            value: Expr::Number(value),
+            span,
+        })
+    };
+    match instruction.as_str().to_ascii_uppercase().as_str() {
+        "CLR" => {
+            Instruction::ArgumentInstruction(ArgumentInstruction::LDA(constant(0)), labels, span)
+        }
+        "INC" => {
+            Instruction::ArgumentInstruction(ArgumentInstruction::ADD(constant(1)), labels, span)
+        }
+        "DEC" => {
+            Instruction::ArgumentInstruction(ArgumentInstruction::SUB(constant(1)), labels, span)
+        }
+        "HLT" => Instruction::Jump(
+            JumpArgument::Location(Operand {
+                value: Expr::Number(instruction_counter as i32),
+                span,
+            }),
+            labels,
+            span,
+        ),
+        _ => unreachable!(),
+    }
+}
+
+/// Expands `BRA`, there is no unconditional relative branch so it becomes a `JMP`, the target
+/// of an offset is computed when the program is generated.
+fn parse_pseudo_branch_instruction<'a>(
+    arg: Pair<'a, Rule>,
+    labels: Vec<Label<'a>>,
+    span: Span,
+) -> Result<Instruction<'a>, Error> {
+    let argument = match parse_branch_argument(arg, span.file)? {
+        BranchArgument::Target(target) => JumpArgument::Location(target),
+        BranchArgument::Offset(offset) => JumpArgument::Offset(offset),
+    };
+    Ok(Instruction::Jump(argument, labels, span))
+}
+
 fn parse_no_arg_instruction<'a>(
     instruction: Pair<'a, Rule>,
-    label: Option<Label<'a>>,
+    labels: Vec<Label<'a>>,
+    span: Span,
 ) -> Instruction<'a> {
-    match instruction.as_str() {
-        "NOP" => Instruction::NoArgumentInstruction(NoArgumentInstruction::NOP, label),
+    match instruction.as_str().to_ascii_uppercase().as_str() {
+        "NOP" => Instruction::NoArgumentInstruction(NoArgumentInstruction::NOP, labels, span),
         _ => unreachable!(),
     }
 }
@@ -76,105 +425,276 @@ fn parse_no_arg_instruction<'a>(
 fn parse_arg_instruction<'a>(
     instruction: Pair<'a, Rule>,
     arg: Pair<'a, Rule>,
-    label: Option<Label<'a>>,
-) -> Instruction<'a> {
-    let arg = parse_argument(arg);
-    match instruction.as_str() {
-        "LDA" => Instruction::ArgumentInstruction(ArgumentInstruction::LDA(arg), label),
-        "ADD" => Instruction::ArgumentInstruction(ArgumentInstruction::ADD(arg), label),
-        "SUB" => Instruction::ArgumentInstruction(ArgumentInstruction::SUB(arg), label),
+    labels: Vec<Label<'a>>,
+    span: Span,
+) -> Result<Instruction<'a>, Error> {
+    let arg = parse_argument(arg, span.file)?;
+    Ok(match instruction.as_str().to_ascii_uppercase().as_str() {
+        "LDA" => Instruction::ArgumentInstruction(ArgumentInstruction::LDA(arg), labels, span),
+        "ADD" => Instruction::ArgumentInstruction(ArgumentInstruction::ADD(arg), labels, span),
+        "SUB" => Instruction::ArgumentInstruction(ArgumentInstruction::SUB(arg), labels, span),
         _ => unreachable!(),
-    }
+    })
 }
 
 fn parse_jump_instruction<'a>(
     instruction: Pair<'a, Rule>,
     arg: Pair<'a, Rule>,
-    label: Option<Label<'a>>,
-) -> Instruction<'a> {
-    let arg = parse_jump_argument(arg);
-    match instruction.as_str() {
-        "JMP" => Instruction::Jump(arg, label),
+    labels: Vec<Label<'a>>,
+    span: Span,
+) -> Result<Instruction<'a>, Error> {
+    let arg = parse_jump_argument(arg, span.file)?;
+    Ok(match instruction.as_str().to_ascii_uppercase().as_str() {
+        "JMP" => Instruction::Jump(arg, labels, span),
         _ => unreachable!(),
-    }
+    })
 }
 
 fn parse_memory_location_instruction<'a>(
     instruction: Pair<'a, Rule>,
     arg: Pair<'a, Rule>,
-    label: Option<Label<'a>>,
-) -> Instruction<'a> {
-    let arg_string = arg.as_str();
-    let arg_value = u8::from_str_radix(&arg_string[1..arg_string.len() - 1], 16).unwrap();
-    match instruction.as_str() {
-        "STA" => {
-            Instruction::MemoryLocationInstruction(MemoryLocationInstruction::STA(arg_value), label)
-        }
+    labels: Vec<Label<'a>>,
+    span: Span,
+) -> Result<Instruction<'a>, Error> {
+    let arg = parse_memory_location(arg, span.file)?;
+    Ok(match instruction.as_str().to_ascii_uppercase().as_str() {
+        "STA" => Instruction::MemoryLocationInstruction(
+            MemoryLocationInstruction::STA(arg),
+            labels,
+            span,
+        ),
         _ => unreachable!(),
-    }
+    })
 }
 
 fn parse_constant_arg_instruction<'a>(
     instruction: Pair<'a, Rule>,
     arg: Pair<'a, Rule>,
-    label: Option<Label<'a>>,
-) -> Instruction<'a> {
-    let arg_value = u8::from_str_radix(&arg.as_str()[1..], 16).unwrap();
-    match instruction.as_str() {
+    labels: Vec<Label<'a>>,
+    span: Span,
+) -> Result<Instruction<'a>, Error> {
+    let arg = parse_branch_argument(arg, span.file)?;
+    Ok(match instruction.as_str().to_ascii_uppercase().as_str() {
         "BRZ" => Instruction::ConstantArgumentInstruction(
-            ConstantArgumentInstruction::BRZ(arg_value),
-            label,
+            ConstantArgumentInstruction::BRZ(arg),
+            labels,
+            span,
         ),
         "BRC" => Instruction::ConstantArgumentInstruction(
-            ConstantArgumentInstruction::BRC(arg_value),
-            label,
+            ConstantArgumentInstruction::BRC(arg),
+            labels,
+            span,
         ),
         "BRN" => Instruction::ConstantArgumentInstruction(
-            ConstantArgumentInstruction::BRN(arg_value),
-            label,
+            ConstantArgumentInstruction::BRN(arg),
+            labels,
+            span,
         ),
         _ => unreachable!(),
-    }
+    })
 }
 
-fn parse_argument<'a>(argument: Pair<'a, Rule>) -> Argument {
+fn parse_argument<'a>(argument: Pair<'a, Rule>, file: FileId) -> Result<Argument<'a>, Error> {
     let argument = argument.into_inner().next().unwrap();
     match argument.as_rule() {
-        Rule::memory_location => {
-            let arg_string = argument.as_str();
-            let arg_value = u8::from_str_radix(&arg_string[1..arg_string.len() - 1], 16).unwrap();
-            Argument::MemoryLocation(arg_value)
-        }
-        Rule::digit_literal => {
-            let arg_value = u8::from_str_radix(&argument.as_str()[1..], 16).unwrap();
-            Argument::Constant(arg_value)
-        }
+        Rule::memory_location => Ok(Argument::MemoryLocation(parse_memory_location(
+            argument, file,
+        )?)),
+        Rule::digit_literal => Ok(Argument::Constant(parse_digit_literal(argument, file)?)),
         _ => unreachable!(),
     }
 }
 
-fn parse_label<'a>(label: Pair<'a, Rule>, instruction_counter: u8) -> Label<'a> {
+fn parse_label<'a>(
+    label: Pair<'a, Rule>,
+    instruction_counter: u8,
+    file: FileId,
+) -> Result<Label<'a>, Error> {
     match label.as_rule() {
-        Rule::label => Label {
-            name: label.as_str(),
+        Rule::label => Ok(Label {
+            name: parse_name(&label, file)?,
             location: instruction_counter,
-        },
+            span: Span::new(label.as_span(), file),
+        }),
         _ => unreachable!(),
     }
 }
 
-fn parse_jump_argument<'a>(arg: Pair<'a, Rule>) -> JumpArgument<'a> {
+/// Mnemonics can not be used as names of labels and constants.
+const RESERVED: [&str; 15] = [
+    "NOP", "LDA", "STA", "ADD", "SUB", "JMP", "BRZ", "BRC", "BRN", "DB", "CLR", "INC", "DEC",
+    "HLT", "BRA",
+];
+
+/// Checks that a `label` is neither one of the reserved mnemonics nor a lone hex digit, which
+/// would be read as a number wherever the name is used.
+fn parse_name<'a>(name: &Pair<'a, Rule>, file: FileId) -> Result<&'a str, Error> {
+    let text = name.as_str();
+    let hex_digit = text.len() == 1 && text.chars().all(|c| c.is_ascii_hexdigit());
+    if hex_digit || RESERVED.contains(&text.to_ascii_uppercase().as_str()) {
+        return Err(Error::ReservedName {
+            name: text.to_string(),
+            span: Span::new(name.as_span(), file),
+        });
+    }
+    Ok(text)
+}
+
+fn parse_jump_argument<'a>(arg: Pair<'a, Rule>, file: FileId) -> Result<JumpArgument<'a>, Error> {
     let arg = arg.into_inner().next().unwrap();
     match arg.as_rule() {
-        Rule::label => JumpArgument::Label(arg.as_str()),
-        Rule::jump_location => {
-            JumpArgument::Location(u8::from_str_radix(arg.as_str(), 16).unwrap())
-        },
-        Rule::memory_location => {
-            let arg_string = arg.as_str();
-            let arg_value = u8::from_str_radix(&arg_string[1..arg_string.len() - 1], 16).unwrap();
-            JumpArgument::MemoryLocation(arg_value)
+        Rule::jump_expr => Ok(JumpArgument::Location(parse_operand(arg, file)?)),
+        Rule::memory_location => Ok(JumpArgument::MemoryLocation(parse_memory_location(
+            arg, file,
+        )?)),
+        _ => unreachable!(),
+    }
+}
+
+fn parse_branch_argument<'a>(
+    arg: Pair<'a, Rule>,
+    file: FileId,
+) -> Result<BranchArgument<'a>, Error> {
+    let arg = arg.into_inner().next().unwrap();
+    match arg.as_rule() {
+        Rule::jump_expr => Ok(BranchArgument::Target(parse_operand(arg, file)?)),
+        Rule::digit_literal => Ok(BranchArgument::Offset(parse_digit_literal(arg, file)?)),
+        _ => unreachable!(),
+    }
+}
+
+/// Parses a `(n)` memory location into its address.
+fn parse_memory_location<'a>(
+    location: Pair<'a, Rule>,
+    file: FileId,
+) -> Result<Operand<Expr<'a>>, Error> {
+    let span = Span::new(location.as_span(), file);
+    Ok(Operand {
+        value: parse_expr(location.into_inner().next().unwrap(), file)?,
+        span,
+    })
+}
+
+/// Parses a value of a `.byte` directive, negative values are stored in two's complement.
+fn parse_data_value(value: Pair<Rule>, file: FileId) -> Result<Operand<u8>, Error> {
+    let span = Span::new(value.as_span(), file);
+    let literal = value.as_str();
+    let number = match literal.strip_prefix('-') {
+        Some(magnitude) => -parse_number(magnitude, span)?,
+        None => parse_number(literal, span)?,
+    };
+    if !(-8..=15).contains(&number) {
+        return Err(Error::OperandOutOfRange {
+            value: number,
+            span,
+        });
+    }
+    Ok(Operand {
+        value: (number & 0b1111) as u8,
+        span,
+    })
+}
+
+/// Parses a `#n` digit literal into its value.
+fn parse_digit_literal<'a>(
+    literal: Pair<'a, Rule>,
+    file: FileId,
+) -> Result<Operand<Expr<'a>>, Error> {
+    let span = Span::new(literal.as_span(), file);
+    Ok(Operand {
+        value: parse_expr(literal.into_inner().next().unwrap(), file)?,
+        span,
+    })
+}
+
+fn parse_operand<'a>(expr: Pair<'a, Rule>, file: FileId) -> Result<Operand<Expr<'a>>, Error> {
+    let span = Span::new(expr.as_span(), file);
+    Ok(Operand {
+        value: parse_expr(expr, file)?,
+        span,
+    })
+}
+
+/// Builds the expression tree of an `expr` or `jump_expr`, or any of their terms.
+fn parse_expr<'a>(expr: Pair<'a, Rule>, file: FileId) -> Result<Expr<'a>, Error> {
+    match expr.as_rule() {
+        Rule::expr | Rule::jump_expr => {
+            let mut pairs = expr.into_inner();
+            let mut result = parse_expr(pairs.next().unwrap(), file)?;
+            while let Some(operator) = pairs.next() {
+                let rhs = Box::new(parse_expr(pairs.next().unwrap(), file)?);
+                result = match operator.as_str() {
+                    "+" => Expr::Add(Box::new(result), rhs),
+                    "-" => Expr::Sub(Box::new(result), rhs),
+                    _ => unreachable!(),
+                };
+            }
+            Ok(result)
         }
+        Rule::negation | Rule::jump_negation => Ok(Expr::Neg(Box::new(parse_expr(
+            expr.into_inner().next().unwrap(),
+            file,
+        )?))),
+        Rule::number => Ok(Expr::Number(parse_number(
+            expr.as_str(),
+            Span::new(expr.as_span(), file),
+        )?)),
+        Rule::label => Ok(Expr::Symbol(Operand {
+            value: parse_name(&expr, file)?,
+            span: Span::new(expr.as_span(), file),
+        })),
         _ => unreachable!(),
     }
 }
+
+/// Reads a number written like in an operand, e.g. `12`, `0x0C`, `0b1100` or the lone hex digit
+/// `C`, `None` if `literal` is not a number.
+pub fn parse_literal(literal: &str) -> Option<i32> {
+    let number = AsmParser::parse(Rule::number, literal).ok()?.next()?;
+    if number.as_str() != literal {
+        return None;
+    }
+    parse_number(literal, Span::default()).ok()
+}
+
+/// Converts a `number` literal: `0x` hex, `0b` binary, a lone hex digit or a decimal number.
+fn parse_number(literal: &str, span: Span) -> Result<i32, Error> {
+    let (digits, radix) = if let Some(digits) = literal
+        .strip_prefix("0x")
+        .or_else(|| literal.strip_prefix("0X"))
+    {
+        (digits, 16)
+    } else if let Some(digits) = literal
+        .strip_prefix("0b")
+        .or_else(|| literal.strip_prefix("0B"))
+    {
+        (digits, 2)
+    } else if literal.len() == 1 {
+        (literal, 16)
+    } else {
+        (literal, 10)
+    };
+    i32::from_str_radix(digits, radix).map_err(|_| Error::InvalidLiteral {
+        literal: literal.to_string(),
+        span,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::generate_binary;
+
+    #[test]
+    fn macro_named_like_a_pseudo_instruction() {
+        let mut sources = Sources::new();
+        sources.add(
+            "decmem.asm",
+            ".macro DECMEM CELL\nLDA (CELL)\nDEC\nSTA (CELL)\n.endm\nDECMEM 0xF\nHLT\n",
+        );
+        let ast = parse_asm(&sources).unwrap();
+        let (program, _) = generate_binary(&ast).unwrap();
+        assert_eq!(program.program_memory[..4], [2, 6, 3, 8]);
+        assert_eq!(program.data_memory[..4], [15, 1, 15, 3]);
+    }
+}
@@ -1,13 +1,13 @@
-use crate::generate::insert_label;
 use crate::asm::*;
-use crate::generate::generate_binary;
+use crate::error::Error;
+use crate::generate::{generate_binary, Program};
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
 
 use serde::Serialize;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 pub struct StateRegister {
     pub carry: bool,
     pub zero: bool,
@@ -56,220 +56,728 @@ impl fmt::Display for State {
     }
 }
 
+/// Something noteworthy that happened during a simulation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum Event {
+    /// A `STA` in `step` stored `value` into a cell of the data memory that holds the operand of an
+    /// instruction, which sees the new operand the next time it is executed.
+    OperandOverwritten { step: usize, address: u8, value: u8 },
+}
 
-pub fn simulate<'a>(instructions: Vec<Instruction<'a>>, max_steps: usize) -> Vec<State> {
-    let mut data_memory = generate_binary(instructions.clone()).data_memory;
-
-    let mut labels: HashMap<&str, u8> = HashMap::new();
-
-    let mut states: Vec<State> = Vec::new();
-    let mut step: usize = 0;
-    let mut clk: bool;
-    let mut pc: u8 = 0;
-    let mut addr_bus: u8;
-    let mut data_bus: u8;
-    let mut ir : u8 = 0;
-    let mut dr : u8 = 0;
-    let mut akku : u8 = 0;
-    let mut sr: StateRegister = StateRegister {
-        carry: false,
-        zero: false,
-        negative: false
-    };
-
-    for instruction in instructions.iter() {
-        match instruction {
-            Instruction::NoArgumentInstruction(_, label)
-            | Instruction::MemoryLocationInstruction(_, label)
-            | Instruction::ConstantArgumentInstruction(_, label)
-            | Instruction::ArgumentInstruction(_, label)
-            | Instruction::Jump(_, label) => {
-                insert_label(&mut labels, label);
-            }
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::OperandOverwritten { step, address, value } => write!(
+                f,
+                "step {}: stored {} into the operand of the instruction at address {}",
+                step, value, address
+            ),
+        }
+    }
+}
+
+/// A flag of the [`StateRegister`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Zero,
+    Negative,
+}
+
+impl StateRegister {
+    pub fn flag(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::Carry => self.carry,
+            Flag::Zero => self.zero,
+            Flag::Negative => self.negative,
         }
     }
+}
 
-    let mut next_pc: Option<u8> = None;
-    let mut next_akku = 0;
-    let mut next_carry = false;
-    let mut next_data_mem_addr: Option<usize> = None;
-    let mut next_data_mem_val: Option<u8> = None;
-    loop {
-        if next_akku != akku {
-            next_carry = (next_akku & (1<<4)) != 0;
+/// Stops [`Simulator::run`] before the instruction at `address` is executed, if `condition` holds.
+///
+/// Without an address it stops before any instruction, without a condition it always stops.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: Option<u8>,
+    /// A flag and the value it has to have.
+    pub condition: Option<(Flag, bool)>,
+}
+
+impl Breakpoint {
+    /// Stops before the instruction at `address`, use [`Ast::label`] for the address of a label.
+    pub fn at(address: u8) -> Self {
+        Breakpoint {
+            address: Some(address),
+            condition: None,
         }
+    }
 
-        if let Some(mut next_pc_value) = next_pc {
-            next_pc_value = next_pc_value % 16;
-            next_akku = next_akku % 16;
-            pc = next_pc_value;
-            next_pc = None;
+    /// Stops before the first instruction that is fetched while `flag` is `value`.
+    pub fn when(flag: Flag, value: bool) -> Self {
+        Breakpoint {
+            address: None,
+            condition: Some((flag, value)),
         }
+    }
+
+    fn hit(&self, pc: u8, sr: StateRegister) -> bool {
+        self.address.unwrap_or(pc) == pc
+            && self
+                .condition
+                .iter()
+                .all(|(flag, value)| sr.flag(*flag) == *value)
+    }
+}
+
+/// The kind of data memory access a [`Watchpoint`] stops at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch {
+    /// `LDA (n)`, `ADD (n)`, `SUB (n)` or `JMP (n)` reads the cell.
+    Read,
+    /// `STA (n)` writes the cell.
+    Write,
+    /// `STA (n)` writes a different value into the cell.
+    Change,
+}
+
+/// Stops [`Simulator::run`] after an instruction accessed the data memory cell at `address`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub address: u8,
+    pub watch: Watch,
+}
+
+/// An access to the data memory by the instruction executed last, `old` and `new` are the same for
+/// a read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub address: u8,
+    pub write: bool,
+    pub old: u8,
+    pub new: u8,
+}
 
+impl Watchpoint {
+    fn hit(&self, access: Access) -> bool {
+        access.address == self.address
+            && match self.watch {
+                Watch::Read => !access.write,
+                Watch::Write => access.write,
+                Watch::Change => access.write && access.old != access.new,
+            }
+    }
+}
 
-        akku = next_akku;
+/// Why a program can not do anything new anymore.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Halt {
+    /// The instruction at `address` is a `JMP` to itself, e.g. a `HLT`.
+    JumpToSelf { address: u8 },
+    /// The whole machine state is the same as after an earlier instruction, so the program loops
+    /// forever, e.g. an idle loop or an instruction that changes nothing.
+    Loop,
+    /// The program counter left the program and only `NOP`s follow.
+    EndOfProgram,
+}
 
-        if let Some(addr) = next_data_mem_addr {
-            data_memory[addr] = next_data_mem_val.unwrap();
-            next_data_mem_addr = None;
-            next_data_mem_val = None;
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Halt::JumpToSelf { address } => {
+                write!(f, "the instruction at address {} jumps to itself", address)
+            }
+            Halt::Loop => write!(f, "the machine state repeats, the program loops forever"),
+            Halt::EndOfProgram => write!(f, "the program counter ran past the end of the program"),
         }
+    }
+}
 
-        let instruction = if pc as usize > instructions.len() - 1 {
-            Instruction::NoArgumentInstruction(NoArgumentInstruction::NOP, None)
-        } else {
-            instructions[pc as usize]
-        };
+/// Why [`Simulator::run`] stopped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(Breakpoint),
+    Watchpoint(Watchpoint, Access),
+    Halted(Halt),
+    /// The given number of steps ran without hitting anything.
+    StepLimit,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(breakpoint) => {
+                write!(f, "breakpoint")?;
+                if let Some(address) = breakpoint.address {
+                    write!(f, " at address {}", address)?;
+                }
+                if let Some((flag, value)) = breakpoint.condition {
+                    write!(f, " when {:?} is {}", flag, value as u8)?;
+                }
+                Ok(())
+            }
+            Stop::Watchpoint(_, access) if access.write => write!(
+                f,
+                "watchpoint, address {} changed from {} to {}",
+                access.address, access.old, access.new
+            ),
+            Stop::Watchpoint(_, access) => write!(
+                f,
+                "watchpoint, address {} was read, it holds {}",
+                access.address, access.old
+            ),
+            Stop::Halted(halt) => write!(f, "halted, {}", halt),
+            Stop::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+pub fn simulate(ast: &Ast, max_steps: usize) -> Result<Vec<State>, Error> {
+    let (program, _) = generate_binary(ast)?;
+    Ok(simulate_program(&program, max_steps))
+}
+
+/// Simulates an assembled program, e.g. a memory dump, decoding every instruction when it is fetched.
+pub fn simulate_program(program: &Program, max_steps: usize) -> Vec<State> {
+    simulate_program_with_events(program, max_steps).0
+}
 
-        let binary_instruction: BinaryInstruction = match instruction {
-            Instruction::NoArgumentInstruction(instruction, _) => instruction.into(),
-            Instruction::MemoryLocationInstruction(instruction, _) => instruction.into(),
-            Instruction::ConstantArgumentInstruction(instruction, _) => instruction.into(),
-            Instruction::ArgumentInstruction(instruction, _) => instruction.into(),
-            Instruction::Jump(argument, _) => match argument {
-                JumpArgument::Location(arg) => BinaryInstruction {
-                    opcode: 8,
-                    argument: arg,
+/// Like [`simulate_program`], but also reports the [`Event`]s of the simulation.
+pub fn simulate_program_with_events(program: &Program, max_steps: usize) -> (Vec<State>, Vec<Event>) {
+    let mut simulator = Simulator::new(program);
+    let states = simulator.run_until(|simulator| simulator.step() == max_steps);
+    (states, simulator.events)
+}
+
+/// The machine state of a running program, advanced one half cycle at a time.
+///
+/// Every instruction takes two half cycles: it is fetched while the clock is low and executed when
+/// it is high. The results of an instruction, e.g. the new accumulator, become visible at the start
+/// of the next instruction. The operand of an instruction is read from the data memory when it is
+/// fetched, so a program can modify its own operands with `STA`.
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    program_memory: [u8; 16],
+    data_memory: [u8; 16],
+    program_length: usize,
+    registers: Registers,
+
+    events: Vec<Event>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// What every half cycle and setter so far changed, to step backwards.
+    history: Vec<Delta>,
+}
+
+/// Everything of a [`Simulator`] but the memories.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Registers {
+    step: usize,
+    clk: bool,
+    pc: u8,
+    addr_bus: u8,
+    data_bus: u8,
+    ir: u8,
+    dr: u8,
+    akku: u8,
+    sr: StateRegister,
+    /// The instruction fetched in the low half of the current step.
+    fetched: BinaryInstruction,
+    opcode_info: Option<OpcodeInfo>,
+
+    next_pc: Option<u8>,
+    next_akku: u8,
+    next_carry: bool,
+    next_data_mem: Option<(usize, u8)>,
+    /// Flags set with [`Simulator::set_sr`], the next instruction uses them instead of computing
+    /// them from the accumulator.
+    next_sr: Option<StateRegister>,
+    /// The data memory access of the instruction executed last.
+    access: Option<Access>,
+}
+
+/// The changes of a single half cycle or setter, with the values from before it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Delta {
+    registers: Registers,
+    /// The address and old value of the data memory cell that was written.
+    write: Option<(usize, u8)>,
+    /// The number of events before the change.
+    events: usize,
+}
+
+/// The part of a [`Simulator`] compared to find a loop.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+struct Machine {
+    data_memory: [u8; 16],
+    pc: u8,
+    ir: u8,
+    dr: u8,
+    akku: u8,
+    sr: StateRegister,
+    next_pc: Option<u8>,
+    next_akku: u8,
+    next_carry: bool,
+    next_data_mem: Option<(usize, u8)>,
+    next_sr: Option<StateRegister>,
+}
+
+impl Simulator {
+    pub fn new(program: &Program) -> Self {
+        Simulator {
+            program_memory: program.program_memory,
+            data_memory: program.data_memory,
+            program_length: program.program_length(),
+            registers: Registers {
+                step: 0,
+                // The last half cycle was high, so the first one fetches.
+                clk: true,
+                pc: 0,
+                addr_bus: 0,
+                data_bus: 0,
+                ir: 0,
+                dr: 0,
+                akku: 0,
+                sr: StateRegister {
+                    carry: false,
+                    zero: false,
+                    negative: false,
                 },
-                JumpArgument::Label(arg) => {
-                    if let Some(address) = labels.get(arg) {
-                        BinaryInstruction {
-                            opcode: 8,
-                            argument: *address,
-                        }
-                    } else {
-                        panic!("Tried to JMP to label: {}, which does not exist", arg);
-                    }
+                fetched: BinaryInstruction {
+                    opcode: 0,
+                    argument: 0,
                 },
-                JumpArgument::MemoryLocation(address) => {
-                    BinaryInstruction {
-                        opcode: 12,
-                        argument: address
-                    }
-                }
+                opcode_info: None,
+                next_pc: None,
+                next_akku: 0,
+                next_carry: false,
+                next_data_mem: None,
+                next_sr: None,
+                access: None,
             },
-        };
+            events: Vec::new(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            history: Vec::new(),
+        }
+    }
 
-        clk = false;
+    /// The number of instructions executed so far.
+    pub fn step(&self) -> usize {
+        self.registers.step
+    }
 
-        addr_bus = pc;
-        data_bus = data_memory[pc as usize];
-        let opcode_info = match instruction {
-            Instruction::MemoryLocationInstruction(instruction, _) => {
-                match instruction {
-                    MemoryLocationInstruction::STA(location) => Some(OpcodeInfo{addr: location, content: data_memory[location as usize]}),
-                }
-            },
-            Instruction::ArgumentInstruction(instruction, _) => {
-                match instruction {
-                    ArgumentInstruction::ADD(argument) | ArgumentInstruction::SUB(argument) | ArgumentInstruction::LDA(argument) => match argument {
-                        Argument::MemoryLocation(location) => Some(OpcodeInfo{addr: location, content: data_memory[location as usize]}),
-                        _ => None
-                    }
-                }
-            },
-            Instruction::Jump(JumpArgument::MemoryLocation(location), _) => {
-                Some(OpcodeInfo{
-                    addr: location,
-                    content: data_memory[location as usize]
-                })
-            }
-            _ => None
+    /// The clock level of the last half cycle.
+    pub fn clk(&self) -> bool {
+        self.registers.clk
+    }
+
+    pub fn pc(&self) -> u8 {
+        self.registers.pc
+    }
+
+    /// The address of the instruction that runs next, after a low half cycle the one it fetched.
+    ///
+    /// Unlike [`Simulator::pc`] this already follows a jump that has not been fetched yet.
+    pub fn instruction_address(&self) -> u8 {
+        match self.registers.next_pc {
+            Some(next_pc) if self.registers.clk => next_pc % 16,
+            _ => self.registers.pc,
+        }
+    }
+
+    pub fn akku(&self) -> u8 {
+        self.registers.akku
+    }
+
+    pub fn sr(&self) -> StateRegister {
+        self.registers.sr
+    }
+
+    pub fn ir(&self) -> u8 {
+        self.registers.ir
+    }
+
+    pub fn dr(&self) -> u8 {
+        self.registers.dr
+    }
+
+    pub fn data_memory(&self) -> &[u8; 16] {
+        &self.data_memory
+    }
+
+    pub fn program_memory(&self) -> &[u8; 16] {
+        &self.program_memory
+    }
+
+    /// Everything noteworthy that happened so far, in order.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> Breakpoint {
+        self.breakpoints.remove(index)
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Watchpoint {
+        self.watchpoints.remove(index)
+    }
+
+    /// The state after the last half cycle, its step is the one of the instruction it belongs to.
+    pub fn state(&self) -> State {
+        let step = if self.registers.clk {
+            self.registers.step.saturating_sub(1)
+        } else {
+            self.registers.step
         };
-        states.push(State{
+        State {
             step,
-            clk,
-            pc,
-            addr_bus,
-            data_bus,
-            ir,
-            dr,
-            akku,
-            sr,
-            opcode_info
+            clk: self.registers.clk,
+            pc: self.registers.pc,
+            addr_bus: self.registers.addr_bus,
+            data_bus: self.registers.data_bus,
+            ir: self.registers.ir,
+            dr: self.registers.dr,
+            akku: self.registers.akku,
+            sr: self.registers.sr,
+            opcode_info: self.registers.opcode_info,
+        }
+    }
+
+    /// The memories as they are now, e.g. to disassemble them.
+    pub fn program(&self) -> Program {
+        Program {
+            data_memory: self.data_memory,
+            program_memory: self.program_memory,
+        }
+    }
+
+    /// Sets the program counter, also for the next instruction if it has not been fetched yet.
+    /// After a low half cycle the instruction at the new address replaces the fetched one.
+    ///
+    /// Like all setters this is recorded like a half cycle, so stepping back undoes it.
+    pub fn set_pc(&mut self, pc: u8) {
+        self.record(None);
+        let pc = pc & 0b1111;
+        self.registers.pc = pc;
+        if self.registers.next_pc.is_some() {
+            self.registers.next_pc = Some(pc);
+        }
+        if !self.registers.clk {
+            self.load_instruction();
+        }
+    }
+
+    /// Sets the accumulator, also for the next instruction if it has not been fetched yet.
+    pub fn set_akku(&mut self, akku: u8) {
+        self.record(None);
+        self.registers.akku = akku & 0b1111;
+        self.registers.next_akku = akku & 0b1111;
+    }
+
+    /// Sets the flags, the next instruction uses them instead of the ones it would compute from
+    /// the accumulator, e.g. to decide a branch.
+    pub fn set_sr(&mut self, sr: StateRegister) {
+        self.record(None);
+        self.registers.sr = sr;
+        self.registers.next_sr = Some(sr);
+    }
+
+    pub fn set_data_memory(&mut self, address: u8, value: u8) {
+        let address = (address & 0b1111) as usize;
+        self.record(Some((address, self.data_memory[address])));
+        self.data_memory[address] = value & 0b1111;
+    }
+
+    /// Adds the registers and events as they are now to the history, `write` is the address and
+    /// old value of a data memory cell that is about to change.
+    fn record(&mut self, write: Option<(usize, u8)>) {
+        self.history.push(Delta {
+            registers: self.registers,
+            write,
+            events: self.events.len(),
         });
+    }
 
-        clk = true;
+    /// Runs the next half cycle and returns the state after it.
+    pub fn step_half_cycle(&mut self) -> State {
+        self.record(match self.registers.next_data_mem {
+            // The store of the last instruction happens when the next one is fetched.
+            Some((address, _)) if self.registers.clk => Some((address, self.data_memory[address])),
+            _ => None,
+        });
 
-        sr.carry = next_carry;
-        sr.zero = akku == 0 || akku == (1<<4);
-        sr.negative = (akku & (1<<3)) != 0;
+        if self.registers.clk {
+            self.fetch();
+        } else {
+            self.execute();
+            self.registers.step += 1;
+        }
+        self.state()
+    }
 
-        dr = binary_instruction.argument;
-        ir = binary_instruction.opcode;
+    /// Runs the rest of the current instruction, or all of the next one, and returns its states.
+    pub fn step_instruction(&mut self) -> Vec<State> {
+        let mut states = vec![self.step_half_cycle()];
+        if !self.registers.clk {
+            states.push(self.step_half_cycle());
+        }
+        states
+    }
 
-        addr_bus = dr;
+    /// Undoes the last half cycle or setter and returns the state before it, `None` at the start.
+    pub fn step_back_half_cycle(&mut self) -> Option<State> {
+        let delta = self.history.pop()?;
+        self.registers = delta.registers;
+        if let Some((address, value)) = delta.write {
+            self.data_memory[address] = value;
+        }
+        self.events.truncate(delta.events);
+        Some(self.state())
+    }
 
-        match instruction {
-            Instruction::NoArgumentInstruction(instruction, _) => match instruction {
-                NoArgumentInstruction::NOP => {}
-            },
-            Instruction::ConstantArgumentInstruction(instruction, _) => match instruction {
-                ConstantArgumentInstruction::BRC(arg) if sr.carry => next_pc = Some(pc + arg),
-                ConstantArgumentInstruction::BRN(arg) if sr.negative => next_pc = Some(pc + arg),
-                ConstantArgumentInstruction::BRZ(arg) if sr.zero => next_pc = Some(pc + arg),
-                _ => {}
-            },
-            Instruction::Jump(arg, _) => match arg {
-                JumpArgument::Label(label) => {
-                    next_pc = Some(*labels.get(label).unwrap());
-                    addr_bus = *labels.get(label).unwrap();
-                },
-                JumpArgument::Location(location) => {
-                    next_pc = Some(location);
-                    addr_bus = location;
-                },
-                JumpArgument::MemoryLocation(location) => {
-                    next_pc = Some(data_memory[location as usize]);
-                    addr_bus = data_memory[location as usize];
+    /// Undoes the last instruction, or the half of one that ran, and returns the state before it,
+    /// `None` at the start.
+    pub fn step_back_instruction(&mut self) -> Option<State> {
+        let mut state = self.step_back_half_cycle()?;
+        if !self.registers.clk {
+            state = self.step_back_half_cycle()?;
+        }
+        Some(state)
+    }
+
+    /// Moves to the state after `step` instructions, backwards by undoing the recorded half cycles
+    /// and forwards by running the program.
+    pub fn goto_step(&mut self, step: usize) -> State {
+        while self.registers.step > step || !self.registers.clk {
+            if self.step_back_half_cycle().is_none() {
+                break;
+            }
+        }
+        while self.registers.step < step {
+            self.step_instruction();
+        }
+        self.state()
+    }
+
+    /// Runs instructions until `predicate` holds after one of them and returns all their states.
+    ///
+    /// At least one instruction is run, even if `predicate` already holds.
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&Simulator) -> bool) -> Vec<State> {
+        let mut states = Vec::new();
+        loop {
+            states.extend(self.step_instruction());
+            if predicate(self) {
+                return states;
+            }
+        }
+    }
+
+    /// Runs at most `max_steps` instructions, stopping early at a breakpoint, a watchpoint or when
+    /// the program halts.
+    ///
+    /// A breakpoint stops after the low half cycle that fetched the instruction, so the instruction
+    /// has not been executed yet, a watchpoint after the high half cycle of the accessing instruction.
+    pub fn run(&mut self, max_steps: usize) -> (Vec<State>, Stop) {
+        let last_step = self.registers.step + max_steps;
+        let mut states = Vec::new();
+        let mut seen = HashSet::new();
+        while self.registers.step < last_step {
+            states.push(self.step_half_cycle());
+            if !self.registers.clk {
+                let (pc, sr) = (self.registers.pc, self.registers.sr);
+                if let Some(breakpoint) = self.breakpoints.iter().find(|b| b.hit(pc, sr)) {
+                    return (states, Stop::Breakpoint(*breakpoint));
                 }
-            },
-            Instruction::MemoryLocationInstruction(arg, _) => match arg {
-                MemoryLocationInstruction::STA(arg) => {
-                    next_data_mem_addr = Some(arg as usize);
-                    next_data_mem_val = Some(akku);
+                if pc as usize >= self.program_length {
+                    return (states, Stop::Halted(Halt::EndOfProgram));
                 }
-            },
-            Instruction::ArgumentInstruction(instruction, _) => match instruction {
-                ArgumentInstruction::LDA(arg) => match arg {
-                    Argument::MemoryLocation(location) => next_akku = data_memory[location as usize],
-                    Argument::Constant(val) => next_akku = val
-                },
-                ArgumentInstruction::ADD(arg) => match arg {
-                    Argument::MemoryLocation(location) => next_akku = akku + data_memory[location as usize],
-                    Argument::Constant(val) => next_akku = akku + val
-                },
-                ArgumentInstruction::SUB(arg) => match arg {
-                    Argument::MemoryLocation(location) => next_akku = akku + (data_memory[location as usize] ^ 0b1111) + 1,
-                    Argument::Constant(val) => next_akku = akku + (val ^ 0b1111) + 1
+                if self.registers.fetched.opcode == 8 && self.registers.fetched.argument == pc {
+                    return (states, Stop::Halted(Halt::JumpToSelf { address: pc }));
+                }
+            } else {
+                if let Some(access) = self.registers.access {
+                    if let Some(watchpoint) = self.watchpoints.iter().find(|w| w.hit(access)) {
+                        return (states, Stop::Watchpoint(*watchpoint, access));
+                    }
+                }
+                if !seen.insert(self.machine()) {
+                    return (states, Stop::Halted(Halt::Loop));
                 }
             }
         }
+        (states, Stop::StepLimit)
+    }
 
-        data_bus = data_memory[addr_bus as usize];
+    /// Everything that decides what the next instructions do.
+    fn machine(&self) -> Machine {
+        Machine {
+            data_memory: self.data_memory,
+            pc: self.registers.pc,
+            ir: self.registers.ir,
+            dr: self.registers.dr,
+            akku: self.registers.akku,
+            sr: self.registers.sr,
+            next_pc: self.registers.next_pc,
+            next_akku: self.registers.next_akku,
+            next_carry: self.registers.next_carry,
+            next_data_mem: self.registers.next_data_mem,
+            next_sr: self.registers.next_sr,
+        }
+    }
 
-        if let None = next_pc {
-            next_pc = Some(pc + 1);
+    /// The low half cycle: applies the results of the last instruction and fetches the next one.
+    fn fetch(&mut self) {
+        if self.registers.next_akku != self.registers.akku {
+            self.registers.next_carry = (self.registers.next_akku & (1 << 4)) != 0;
         }
 
-        states.push(State{
-            step,
-            clk,
-            pc,
-            addr_bus,
-            data_bus,
-            ir,
-            dr,
-            akku,
-            sr,
-            opcode_info
-        });
+        if let Some(next_pc) = self.registers.next_pc.take() {
+            self.registers.pc = next_pc % 16;
+            self.registers.next_akku %= 16;
+        }
+
+        self.registers.akku = self.registers.next_akku;
 
-        step += 1;
-        if step == max_steps {
-            break;
+        if let Some((addr, val)) = self.registers.next_data_mem.take() {
+            self.data_memory[addr] = val;
         }
+
+        self.registers.clk = false;
+        self.load_instruction();
     }
 
-    return states;
+    /// Reads the instruction at the program counter into the fetched instruction and the buses.
+    fn load_instruction(&mut self) {
+        // The program memory already has all labels and branch offsets resolved.
+        self.registers.fetched = BinaryInstruction {
+            opcode: self.program_memory[self.registers.pc as usize],
+            argument: self.data_memory[self.registers.pc as usize],
+        };
+
+        self.registers.addr_bus = self.registers.pc;
+        self.registers.data_bus = self.data_memory[self.registers.pc as usize];
+        self.registers.opcode_info = match self.registers.fetched.opcode {
+            // LDA (n), STA (n), ADD (n), SUB (n) and JMP (n) access the data memory
+            2 | 3 | 5 | 7 | 12 => Some(OpcodeInfo {
+                addr: self.registers.fetched.argument,
+                content: self.data_memory[self.registers.fetched.argument as usize],
+            }),
+            _ => None,
+        };
+    }
+
+    /// The high half cycle: updates the state register and executes the fetched instruction.
+    fn execute(&mut self) {
+        self.registers.clk = true;
+
+        let akku = self.registers.akku;
+        match self.registers.next_sr.take() {
+            Some(sr) => self.registers.sr = sr,
+            None => {
+                self.registers.sr.carry = self.registers.next_carry;
+                self.registers.sr.zero = akku == 0 || akku == (1 << 4);
+                self.registers.sr.negative = (akku & (1 << 3)) != 0;
+            }
+        }
+
+        self.registers.dr = self.registers.fetched.argument;
+        self.registers.ir = self.registers.fetched.opcode;
+        let dr = self.registers.dr;
+        let pc = self.registers.pc;
+
+        self.registers.addr_bus = dr;
+
+        let location = dr as usize;
+        self.registers.access = match self.registers.ir {
+            2 | 5 | 7 | 12 => Some(Access {
+                address: dr,
+                write: false,
+                old: self.data_memory[location],
+                new: self.data_memory[location],
+            }),
+            3 => Some(Access {
+                address: dr,
+                write: true,
+                old: self.data_memory[location],
+                new: akku,
+            }),
+            _ => None,
+        };
+        match self.registers.ir {
+            1 => self.registers.next_akku = dr,
+            2 => self.registers.next_akku = self.data_memory[location],
+            3 => {
+                self.registers.next_data_mem = Some((location, akku));
+                // `NOP` and the unused opcodes 13 to 15 never read their operand.
+                if (1..=12).contains(&self.program_memory[location]) {
+                    self.events.push(Event::OperandOverwritten {
+                        step: self.registers.step,
+                        address: dr,
+                        value: akku,
+                    });
+                }
+            }
+            4 => self.registers.next_akku = akku + dr,
+            5 => self.registers.next_akku = akku + self.data_memory[location],
+            6 => self.registers.next_akku = akku + (dr ^ 0b1111) + 1,
+            7 => self.registers.next_akku = akku + (self.data_memory[location] ^ 0b1111) + 1,
+            8 => {
+                self.registers.next_pc = Some(dr);
+                self.registers.addr_bus = dr;
+            }
+            9 if self.registers.sr.zero => self.registers.next_pc = Some(pc + dr),
+            10 if self.registers.sr.carry => self.registers.next_pc = Some(pc + dr),
+            11 if self.registers.sr.negative => self.registers.next_pc = Some(pc + dr),
+            12 => {
+                self.registers.next_pc = Some(self.data_memory[location]);
+                self.registers.addr_bus = self.data_memory[location];
+            }
+            // NOP, branches that are not taken and unused opcodes
+            _ => {}
+        }
+
+        self.registers.data_bus = self.data_memory[self.registers.addr_bus as usize];
+
+        if self.registers.next_pc.is_none() {
+            self.registers.next_pc = Some(pc + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_loop_halts() {
+        // LDA #1 / L: NOP / JMP L
+        let mut program_memory = [0; 16];
+        let mut data_memory = [0; 16];
+        program_memory[..3].copy_from_slice(&[1, 0, 8]);
+        data_memory[..3].copy_from_slice(&[1, 0, 1]);
+        let mut simulator = Simulator::new(&Program {
+            data_memory,
+            program_memory,
+        });
+        let (_, stop) = simulator.run(1000);
+        assert_eq!(stop, Stop::Halted(Halt::Loop));
+        assert!(simulator.step() < 10);
+    }
 }
@@ -0,0 +1,208 @@
+use crate::asm::Span;
+use crate::error::Error;
+use crate::parse::{AsmParser, Rule};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use pest::Parser;
+
+/// Index of a file in [`Sources`], the first file added gets `0`.
+pub type FileId = usize;
+
+/// Reads the files named in `.include` directives.
+pub trait Loader {
+    /// Turns `path` as written in an `.include` in the file `from` into the name of a file.
+    fn resolve(&self, path: &str, _from: &str) -> String {
+        path.to_string()
+    }
+
+    /// Reads the content of the file `name`.
+    fn load(&self, name: &str) -> io::Result<String>;
+}
+
+/// Loads includes from the filesystem, relative to the directory of the including file.
+///
+/// The names are normalized, so every spelling of a path, e.g. `lib/../a.asm` and `a.asm`, names
+/// the same file.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FsLoader;
+
+impl Loader for FsLoader {
+    fn resolve(&self, path: &str, from: &str) -> String {
+        let path = match Path::new(from).parent() {
+            Some(directory) => directory.join(path),
+            None => PathBuf::from(path),
+        };
+        normalize(&path).to_string_lossy().into_owned()
+    }
+
+    fn load(&self, name: &str) -> io::Result<String> {
+        fs::read_to_string(name)
+    }
+}
+
+/// Removes the `.` and `..` components of `path` without looking at the filesystem, only a `..`
+/// at the start is kept.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Loads includes from a fixed set of files kept in memory, e.g. for the browser.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new(files: HashMap<String, String>) -> Self {
+        MemoryLoader { files }
+    }
+
+    pub fn insert(&mut self, name: &str, content: &str) {
+        self.files.insert(name.to_string(), content.to_string());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, name: &str) -> io::Result<String> {
+        self.files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    pub content: String,
+}
+
+/// All files a program consists of, spans refer to them by their [`FileId`].
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+    /// The file an `.include` refers to, keyed by the including file and the offset of the directive.
+    includes: HashMap<(FileId, usize), FileId>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources::default()
+    }
+
+    /// Adds a file without looking at its includes, see [`Sources::load_includes`].
+    pub fn add(&mut self, name: &str, content: &str) -> FileId {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            content: content.to_string(),
+        });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files.iter().enumerate()
+    }
+
+    /// The file included by the `.include` directive starting at `offset` in `file`.
+    pub fn included(&self, file: FileId, offset: usize) -> Option<FileId> {
+        self.includes.get(&(file, offset)).copied()
+    }
+
+    /// Loads everything `file` includes, directly or through other includes, using `loader`.
+    pub fn load_includes(&mut self, file: FileId, loader: &dyn Loader) -> Result<(), Error> {
+        self.load_includes_from(file, loader, &mut vec![file])
+    }
+
+    /// `stack` holds the chain of files that led to `file`, including one of them again is a cycle.
+    fn load_includes_from(
+        &mut self,
+        file: FileId,
+        loader: &dyn Loader,
+        stack: &mut Vec<FileId>,
+    ) -> Result<(), Error> {
+        let directives: Vec<(usize, String, Span)> =
+            AsmParser::parse(Rule::program, &self.files[file].content)
+                .map_err(|e| Error::Grammar(Box::new(e), file))?
+                .filter(|pair| pair.as_rule() == Rule::include_directive)
+                .map(|directive| {
+                    let path = directive.clone().into_inner().next().unwrap();
+                    (
+                        directive.as_span().start(),
+                        path.as_str().to_string(),
+                        Span::new(path.as_span(), file),
+                    )
+                })
+                .collect();
+
+        for (offset, path, span) in directives {
+            let name = loader.resolve(&path, &self.files[file].name);
+            let included = match self.files.iter().position(|source| source.name == name) {
+                Some(included) if stack.contains(&included) => {
+                    return Err(Error::IncludeCycle { path, span })
+                }
+                Some(included) => included,
+                None => {
+                    let content = loader.load(&name).map_err(|e| Error::IncludeFailed {
+                        path,
+                        message: e.to_string(),
+                        span,
+                    })?;
+                    let included = self.add(&name, &content);
+                    stack.push(included);
+                    self.load_includes_from(included, loader, stack)?;
+                    stack.pop();
+                    included
+                }
+            };
+            self.includes.insert((file, offset), included);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_cycle_through_different_spellings() {
+        let directory = std::env::temp_dir().join("hm-asm-include-cycle");
+        fs::create_dir_all(directory.join("dir")).unwrap();
+        let a = directory.join("a.asm");
+        fs::write(&a, ".include \"dir/../dir/b.asm\"\n").unwrap();
+        fs::write(directory.join("dir/b.asm"), ".include \"./../a.asm\"\n").unwrap();
+
+        let name = a.to_string_lossy();
+        let mut sources = Sources::new();
+        let file = sources.add(&name, &fs::read_to_string(&a).unwrap());
+        let result = sources.load_includes(file, &FsLoader);
+        fs::remove_dir_all(&directory).unwrap();
+        match result {
+            Err(Error::IncludeCycle { path, .. }) => assert_eq!(path, "./../a.asm"),
+            result => panic!("expected an include cycle, got {:?}", result),
+        }
+    }
+}
@@ -1,19 +1,40 @@
-use pest::Parser;
+use std::collections::HashMap;
+
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
 use hm_asm_simulator::{
-    generate::generate_binary,
-    parse::{parse_asm, AsmParser, Rule},
+    diagnostics::Diagnostic,
+    generate::{generate_binary, Program},
+    parse::parse_asm,
+    simulate::Simulator,
+    source::{MemoryLoader, Sources},
 };
 
 
+#[derive(Serialize)]
+struct Assembled {
+    #[serde(flatten)]
+    program: Program,
+    warnings: Vec<String>,
+}
+
 #[wasm_bindgen]
 pub fn simulate(code: &str, cycles: usize) -> JsValue {
-    let instructions = parse_asm(match AsmParser::parse(Rule::program, &code) {
-        Ok(instructions) => instructions,
-        Err(e) => return JsValue::from_str(&format!("{}", e))
-    });
-    let states = hm_asm_simulator::simulate::simulate(instructions, cycles);
+    simulate_files(code, JsValue::NULL, cycles)
+}
+
+/// Like `simulate`, `files` maps the names used in `.include` directives to their content.
+#[wasm_bindgen]
+pub fn simulate_files(code: &str, files: JsValue, cycles: usize) -> JsValue {
+    let sources = match load(code, files) {
+        Ok(sources) => sources,
+        Err(e) => return e
+    };
+    let states = match parse_asm(&sources).and_then(|ast| hm_asm_simulator::simulate::simulate(&ast, cycles)) {
+        Ok(states) => states,
+        Err(e) => return JsValue::from_str(&Diagnostic::from(&e).render(&sources))
+    };
 
     JsValue::from_serde(&states).unwrap()
 }
@@ -21,12 +42,99 @@ pub fn simulate(code: &str, cycles: usize) -> JsValue {
 
 #[wasm_bindgen]
 pub fn assemble(code: &str) -> JsValue {
-    let instructions = parse_asm(match AsmParser::parse(Rule::program, &code) {
-        Ok(instructions) => instructions,
-        Err(e) => return JsValue::from_str(&format!("{}", e))
-    });
+    assemble_files(code, JsValue::NULL)
+}
 
+/// Like `assemble`, `files` maps the names used in `.include` directives to their content.
+#[wasm_bindgen]
+pub fn assemble_files(code: &str, files: JsValue) -> JsValue {
+    let sources = match load(code, files) {
+        Ok(sources) => sources,
+        Err(e) => return e
+    };
+    let (program, warnings) = match parse_asm(&sources).and_then(|ast| generate_binary(&ast)) {
+        Ok(binary) => binary,
+        Err(e) => return JsValue::from_str(&Diagnostic::from(&e).render(&sources))
+    };
 
-    let binary = generate_binary(instructions);
+    let binary = Assembled {
+        program,
+        warnings: warnings
+            .iter()
+            .map(|warning| Diagnostic::from(warning).render(&sources))
+            .collect(),
+    };
     JsValue::from_serde(&binary).unwrap()
 }
+
+/// A simulator that steps forwards and backwards through a program one instruction or half cycle
+/// at a time, the states are returned like the ones of `simulate`.
+#[wasm_bindgen]
+pub struct Debugger {
+    simulator: Simulator,
+}
+
+#[wasm_bindgen]
+impl Debugger {
+    /// Assembles `code`, `files` maps the names used in `.include` directives to their content.
+    #[wasm_bindgen(constructor)]
+    pub fn new(code: &str, files: JsValue) -> Result<Debugger, JsValue> {
+        let sources = load(code, files)?;
+        let (program, _) = parse_asm(&sources)
+            .and_then(|ast| generate_binary(&ast))
+            .map_err(|e| JsValue::from_str(&Diagnostic::from(&e).render(&sources)))?;
+        Ok(Debugger {
+            simulator: Simulator::new(&program),
+        })
+    }
+
+    /// The state after the last half cycle.
+    pub fn state(&self) -> JsValue {
+        JsValue::from_serde(&self.simulator.state()).unwrap()
+    }
+
+    pub fn data_memory(&self) -> Vec<u8> {
+        self.simulator.data_memory().to_vec()
+    }
+
+    /// Runs the next half cycle and returns the state after it.
+    pub fn step_half_cycle(&mut self) -> JsValue {
+        JsValue::from_serde(&self.simulator.step_half_cycle()).unwrap()
+    }
+
+    /// Runs the next instruction and returns its states.
+    pub fn step_instruction(&mut self) -> JsValue {
+        JsValue::from_serde(&self.simulator.step_instruction()).unwrap()
+    }
+
+    /// Undoes the last half cycle and returns the state before it, null at the start.
+    pub fn step_back_half_cycle(&mut self) -> JsValue {
+        JsValue::from_serde(&self.simulator.step_back_half_cycle()).unwrap()
+    }
+
+    /// Undoes the last instruction and returns the state before it, null at the start.
+    pub fn step_back_instruction(&mut self) -> JsValue {
+        JsValue::from_serde(&self.simulator.step_back_instruction()).unwrap()
+    }
+
+    /// Moves to the state after `step` instructions and returns it.
+    pub fn goto_step(&mut self, step: usize) -> JsValue {
+        JsValue::from_serde(&self.simulator.goto_step(step)).unwrap()
+    }
+}
+
+/// Collects `code`, called "input", and the files it includes from `files`, which may be null.
+fn load(code: &str, files: JsValue) -> Result<Sources, JsValue> {
+    let files: HashMap<String, String> = if files.is_null() {
+        HashMap::new()
+    } else {
+        files.into_serde().map_err(|e| JsValue::from_str(&e.to_string()))?
+    };
+
+    let mut sources = Sources::new();
+    let input = sources.add("input", code);
+    match sources.load_includes(input, &MemoryLoader::new(files)) {
+        Ok(()) => Ok(sources),
+        Err(e) => Err(JsValue::from_str(&Diagnostic::from(&e).render(&sources))),
+    }
+}